@@ -0,0 +1,140 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+// A bounded queue that never blocks the sender: once full, sending drops the
+// oldest message to make room, so a slow or absent consumer only loses
+// history and always sees the latest messages.
+pub fn bounded<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            queue: VecDeque::with_capacity(capacity.max(1)),
+            senders: 1,
+        }),
+        available: Condvar::new(),
+        capacity: capacity.max(1),
+    });
+
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    available: Condvar,
+    capacity: usize,
+}
+
+struct State<T> {
+    queue: VecDeque<T>,
+    senders: usize,
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    pub fn send(&self, message: T) {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.queue.len() == self.shared.capacity {
+            state.queue.pop_front();
+        }
+        state.queue.push_back(message);
+        self.shared.available.notify_one();
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().unwrap().senders += 1;
+
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().senders -= 1;
+        self.shared.available.notify_all();
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Receiver<T> {
+    // Waits for the next message; `None` once every sender is gone and the
+    // queue is empty.
+    pub fn recv(&self) -> Option<T> {
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            if let Some(message) = state.queue.pop_front() {
+                return Some(message);
+            }
+            if state.senders == 0 {
+                return None;
+            }
+            state = self.shared.available.wait(state).unwrap();
+        }
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<T> {
+        let state = self.shared.state.lock().unwrap();
+        let (mut state, _) = self
+            .shared
+            .available
+            .wait_timeout_while(state, timeout, |state| {
+                state.queue.is_empty() && state.senders > 0
+            })
+            .unwrap();
+
+        state.queue.pop_front()
+    }
+
+    pub fn try_recv(&self) -> Option<T> {
+        self.shared.state.lock().unwrap().queue.pop_front()
+    }
+
+    // Everything queued right now, oldest first, without waiting.
+    pub fn try_iter(&self) -> impl Iterator<Item = T> + '_ {
+        std::iter::from_fn(move || self.try_recv())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_queue_drops_the_oldest() {
+        let (sender, receiver) = bounded(3);
+        for message in 0..5 {
+            sender.send(message);
+        }
+
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![2, 3, 4]);
+        sender.send(5);
+        assert_eq!(receiver.try_recv(), Some(5));
+    }
+
+    #[test]
+    fn recv_ends_when_senders_are_gone() {
+        let (sender, receiver) = bounded(2);
+        let clone = sender.clone();
+        sender.send(1);
+        drop(sender);
+
+        assert_eq!(receiver.recv(), Some(1));
+        assert_eq!(receiver.recv_timeout(Duration::from_millis(1)), None);
+        drop(clone);
+        assert_eq!(receiver.recv(), None);
+    }
+}
@@ -0,0 +1,167 @@
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+// Cue sheet timestamps are minutes:seconds:frames at 75 frames per second.
+const CUE_FRAMES_PER_SECOND: u32 = 75;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CueTrack {
+    pub number: u32,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub start: Duration,
+}
+
+// Reads the tracks of a cue sheet that annotates `audio`. Sheets that span
+// several files, or name a different file, are rejected.
+pub fn load_cue<P: AsRef<Path>, A: AsRef<Path>>(
+    cue_path: P,
+    audio: A,
+) -> anyhow::Result<Vec<CueTrack>> {
+    let (file, tracks) = parse_cue(&fs::read_to_string(cue_path)?)?;
+
+    let file_name = Path::new(&file).file_name();
+    if file_name != audio.as_ref().file_name() {
+        anyhow::bail!(
+            "Cue sheet is for {}, not {}",
+            file,
+            audio.as_ref().display()
+        );
+    }
+
+    Ok(tracks)
+}
+
+// Returns the referenced file and its tracks, ordered as in the sheet.
+pub fn parse_cue(text: &str) -> anyhow::Result<(String, Vec<CueTrack>)> {
+    let mut file: Option<String> = None;
+    let mut tracks: Vec<CueTrack> = vec![];
+    let mut current: Option<CueTrack> = None;
+
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+
+        match command.to_ascii_uppercase().as_str() {
+            "FILE" => {
+                let name = quoted(rest);
+                if file.as_ref().is_some_and(|file| *file != name) {
+                    anyhow::bail!("Cue sheets spanning several files are not supported");
+                }
+                file = Some(name);
+            }
+            "TRACK" => {
+                let number = rest
+                    .split_whitespace()
+                    .next()
+                    .and_then(|number| number.parse().ok())
+                    .ok_or_else(|| anyhow::anyhow!("Bad TRACK on line {}", index + 1))?;
+                if let Some(track) = current.take() {
+                    tracks.push(track);
+                }
+                current = Some(CueTrack {
+                    number,
+                    title: None,
+                    performer: None,
+                    start: Duration::ZERO,
+                });
+            }
+            "TITLE" => {
+                if let Some(track) = current.as_mut() {
+                    track.title = Some(quoted(rest));
+                }
+            }
+            "PERFORMER" => {
+                if let Some(track) = current.as_mut() {
+                    track.performer = Some(quoted(rest));
+                }
+            }
+            // INDEX 01 is where the track itself starts; 00 is the pregap.
+            "INDEX" => {
+                let mut fields = rest.split_whitespace();
+                if fields.next() != Some("01") {
+                    continue;
+                }
+                let start = fields
+                    .next()
+                    .and_then(parse_timestamp)
+                    .ok_or_else(|| anyhow::anyhow!("Bad INDEX on line {}", index + 1))?;
+                if let Some(track) = current.as_mut() {
+                    track.start = start;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(track) = current {
+        tracks.push(track);
+    }
+
+    let file = file.ok_or_else(|| anyhow::anyhow!("Cue sheet names no FILE"))?;
+
+    Ok((file, tracks))
+}
+
+fn quoted(value: &str) -> String {
+    match value
+        .strip_prefix('"')
+        .and_then(|rest| rest.split_once('"'))
+    {
+        Some((inner, _)) => inner.to_string(),
+        None => value.split_whitespace().next().unwrap_or("").to_string(),
+    }
+}
+
+fn parse_timestamp(value: &str) -> Option<Duration> {
+    let mut parts = value.split(':');
+    let minutes = parts.next()?.parse::<u64>().ok()?;
+    let seconds = parts.next()?.parse::<u32>().ok()?;
+    let frames = parts.next()?.parse::<u32>().ok()?;
+    if parts.next().is_some() || seconds >= 60 || frames >= CUE_FRAMES_PER_SECOND {
+        return None;
+    }
+
+    // Minutes come straight from the sheet, so guard the arithmetic.
+    let whole = minutes.checked_mul(60)?.checked_add(seconds as u64)?;
+
+    Some(
+        Duration::from_secs(whole)
+            + Duration::from_secs_f64(frames as f64 / CUE_FRAMES_PER_SECOND as f64),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_tracks_and_start_times() {
+        let (file, tracks) = parse_cue(
+            "FILE \"set.mp3\" MP3\n\
+             TRACK 01 AUDIO\n  TITLE \"Intro\"\n  INDEX 01 00:00:00\n\
+             TRACK 02 AUDIO\n  INDEX 00 04:58:00\n  INDEX 01 05:00:15\n",
+        )
+        .unwrap();
+
+        assert_eq!(file, "set.mp3");
+        assert_eq!(tracks.len(), 2);
+        assert_eq!(tracks[0].title.as_deref(), Some("Intro"));
+        assert_eq!(tracks[1].start, Duration::from_millis(300_200));
+    }
+
+    #[test]
+    fn rejects_timestamps_that_overflow() {
+        assert_eq!(
+            parse_timestamp("71582789:00:00"),
+            Some(Duration::from_secs(71_582_789 * 60))
+        );
+        assert_eq!(parse_timestamp("18446744073709551615:00:00"), None);
+
+        let result =
+            parse_cue("FILE a.wav WAVE\nTRACK 01 AUDIO\nINDEX 01 999999999999999999:00:00\n");
+        assert!(result.is_err_and(|e| e.to_string().contains("Bad INDEX on line 3")));
+    }
+}
@@ -0,0 +1,346 @@
+use minimp3::{Decoder, Error, Frame};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::output::OutputError;
+
+pub struct Decoded {
+    pub samples: Vec<i16>,
+    pub sample_rate: cpal::SampleRate,
+    pub channels: cpal::ChannelCount,
+}
+
+pub fn decode_range<R: Read + Seek>(
+    mut reader: R,
+    start_secs: f64,
+    end_secs: f64,
+    max_duration: Option<Duration>,
+) -> anyhow::Result<Decoded> {
+    if is_wav(&mut reader)? {
+        decode_wav_range(reader, start_secs, end_secs, max_duration)
+    } else {
+        decode_mp3_range(reader, start_secs, end_secs, max_duration)
+    }
+}
+
+// Decodes a whole file to interleaved samples in -1..1 along with its sample
+// rate and channel count, without opening an audio device.
+pub fn decode_all<P: AsRef<Path>>(path: P) -> anyhow::Result<(Vec<f32>, u32, u16)> {
+    let decoded = decode_range(File::open(path)?, 0.0, f64::INFINITY, None)?;
+    let samples = decoded
+        .samples
+        .iter()
+        .map(|sample| *sample as f32 / 32768.0)
+        .collect();
+
+    Ok((samples, decoded.sample_rate.0, decoded.channels))
+}
+
+fn is_wav<R: Read + Seek>(reader: &mut R) -> std::io::Result<bool> {
+    let mut header = [0; 12];
+    let is_wav = match reader.read_exact(&mut header) {
+        Ok(()) => &header[0..4] == b"RIFF" && &header[8..12] == b"WAVE",
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => false,
+        Err(e) => return Err(e),
+    };
+    reader.seek(SeekFrom::Start(0))?;
+
+    Ok(is_wav)
+}
+
+fn decode_wav_range<R: Read + Seek>(
+    reader: R,
+    start_secs: f64,
+    end_secs: f64,
+    max_duration: Option<Duration>,
+) -> anyhow::Result<Decoded> {
+    let mut reader = hound::WavReader::new(reader)?;
+    let spec = reader.spec();
+    let rate = spec.sample_rate as f64;
+
+    // Unlike MP3 the header carries the length, so reject before decoding.
+    if let Some(limit) = max_duration {
+        if reader.duration() as f64 / rate > limit.as_secs_f64() {
+            return Err(OutputError::TooLong { limit }.into());
+        }
+    }
+
+    let start = ((start_secs * rate).round() as u32).min(reader.duration());
+    let end = if end_secs.is_finite() {
+        ((end_secs * rate).round() as u32).min(reader.duration())
+    } else {
+        reader.duration()
+    };
+    reader.seek(start)?;
+
+    let count = end.saturating_sub(start) as usize * spec.channels as usize;
+    let samples = match spec.sample_format {
+        hound::SampleFormat::Float => reader
+            .samples::<f32>()
+            .take(count)
+            .map(|sample| sample.map(|sample| (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16))
+            .collect::<Result<Vec<_>, _>>()?,
+        hound::SampleFormat::Int => {
+            let bits = spec.bits_per_sample as u32;
+            reader
+                .samples::<i32>()
+                .take(count)
+                .map(|sample| {
+                    sample.map(|sample| {
+                        if bits >= 16 {
+                            (sample >> (bits - 16)) as i16
+                        } else {
+                            (sample << (16 - bits)) as i16
+                        }
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?
+        }
+    };
+
+    Ok(Decoded {
+        samples,
+        sample_rate: cpal::SampleRate(spec.sample_rate),
+        channels: spec.channels,
+    })
+}
+
+fn decode_mp3_range<R: Read>(
+    reader: R,
+    start_secs: f64,
+    end_secs: f64,
+    max_duration: Option<Duration>,
+) -> anyhow::Result<Decoded> {
+    let mut decoder = Decoder::new(reader);
+    let mut buffer = Vec::new();
+    let mut sample_rate = cpal::SampleRate(0);
+    let mut channels: cpal::ChannelCount = 1;
+    let mut decoded = 0;
+
+    loop {
+        match decoder.next_frame() {
+            Ok(Frame {
+                data,
+                sample_rate: rate,
+                channels: ch,
+                ..
+            }) => {
+                // Concatenated streams can switch rate or layout between
+                // frames. The buffer is played with one fixed stream config,
+                // so refuse rather than play the rest garbled.
+                let format = (rate as u32, ch as cpal::ChannelCount);
+                if sample_rate.0 != 0 && format != (sample_rate.0, channels) {
+                    let error = OutputError::FormatChanged {
+                        at: Duration::from_secs_f64(
+                            decoded as f64 / (sample_rate.0 as f64 * channels.max(1) as f64),
+                        ),
+                        from: (sample_rate.0, channels),
+                        to: format,
+                    };
+                    log::warn!("{}", error);
+                    return Err(error.into());
+                }
+
+                sample_rate = cpal::SampleRate(rate as u32);
+                channels = ch as cpal::ChannelCount;
+
+                let ch = ch.max(1);
+                let start = (start_secs * rate as f64).round() as usize * ch;
+                let end = if end_secs.is_finite() {
+                    (end_secs * rate as f64).round() as usize * ch
+                } else {
+                    usize::MAX
+                };
+
+                let frame_start = decoded;
+                decoded += data.len();
+
+                let from = start.saturating_sub(frame_start).min(data.len());
+                let to = end.saturating_sub(frame_start).min(data.len());
+                buffer.extend_from_slice(&data[from..to]);
+
+                // MP3 has no reliable length up front, so stop as soon as
+                // the decoded audio crosses the limit.
+                if let Some(limit) = max_duration {
+                    let decoded_secs = buffer.len() as f64 / (rate as f64 * ch as f64);
+                    if decoded_secs > limit.as_secs_f64() {
+                        return Err(OutputError::TooLong { limit }.into());
+                    }
+                }
+
+                if decoded >= end {
+                    break;
+                }
+            }
+            Err(Error::Eof) => break,
+            // A bad or non-audio chunk only loses that frame.
+            Err(Error::SkippedData) | Err(Error::InsufficientData) => continue,
+            Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            // Keep whatever decoded before a corrupt frame rather than
+            // losing the whole file.
+            Err(e) if !buffer.is_empty() => {
+                log::warn!("MP3 decode stopped early: {}", e);
+                break;
+            }
+            Err(e) => return Err(anyhow::anyhow!("MP3 decode failed: {}", e)),
+        }
+    }
+
+    // minimp3 skips anything that isn't a frame, so a file in some other
+    // format decodes to nothing rather than failing.
+    if sample_rate.0 == 0 {
+        anyhow::bail!("MP3 decode failed: no MP3 frames found");
+    }
+
+    Ok(Decoded {
+        samples: buffer,
+        sample_rate,
+        channels,
+    })
+}
+
+// Reports how much of the underlying file has been consumed, as f32 bits.
+struct ProgressReader<R> {
+    inner: R,
+    read: u64,
+    total: u64,
+    progress: Arc<AtomicU32>,
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read += n as u64;
+
+        let fraction = (self.read as f64 / self.total.max(1) as f64).min(1.0) as f32;
+        self.progress.store(fraction.to_bits(), Ordering::Relaxed);
+
+        Ok(n)
+    }
+}
+
+impl<R: Seek> Seek for ProgressReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.read = self.inner.seek(pos)?;
+        Ok(self.read)
+    }
+}
+
+pub struct Loading {
+    progress: Arc<AtomicU32>,
+    crashed: Arc<AtomicBool>,
+    handle: JoinHandle<anyhow::Result<Decoded>>,
+}
+
+impl Loading {
+    pub fn spawn(file: File, max_duration: Option<Duration>) -> Self {
+        let progress = Arc::new(AtomicU32::new(0));
+        let total = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+
+        let reader = ProgressReader {
+            inner: file,
+            read: 0,
+            total,
+            progress: progress.clone(),
+        };
+
+        Self::spawn_decoder(progress, move || {
+            decode_range(reader, 0.0, f64::INFINITY, max_duration)
+        })
+    }
+
+    // Runs `decode` on its own thread. A panic in it becomes a load error
+    // carrying its message, so it reaches the events channel instead of only
+    // stderr.
+    fn spawn_decoder(
+        progress: Arc<AtomicU32>,
+        decode: impl FnOnce() -> anyhow::Result<Decoded> + Send + 'static,
+    ) -> Self {
+        let crashed = Arc::new(AtomicBool::new(false));
+        let flag = crashed.clone();
+
+        let handle = thread::spawn(move || {
+            panic::catch_unwind(AssertUnwindSafe(decode)).unwrap_or_else(|payload| {
+                flag.store(true, Ordering::Relaxed);
+                let message = payload
+                    .downcast_ref::<&str>()
+                    .map(|message| message.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| String::from("unknown panic"));
+                Err(anyhow::anyhow!("Decoder crashed: {}", message))
+            })
+        });
+
+        Self {
+            progress,
+            crashed,
+            handle,
+        }
+    }
+
+    pub fn crashed(&self) -> bool {
+        self.crashed.load(Ordering::Relaxed)
+    }
+
+    pub fn progress(&self) -> f32 {
+        f32::from_bits(self.progress.load(Ordering::Relaxed))
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+
+    pub fn join(self) -> anyhow::Result<Decoded> {
+        self.handle
+            .join()
+            .map_err(|_| anyhow::anyhow!("Decoder thread panicked"))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // An empty stream has no RIFF header, so it goes to minimp3, which finds
+    // no frames in it just as it finds none in a text file.
+    #[test]
+    fn rejects_input_without_mp3_frames() {
+        let result = decode_range(Cursor::new(vec![]), 0.0, f64::INFINITY, None);
+
+        assert!(result.is_err_and(|e| e.to_string().contains("no MP3 frames")));
+    }
+
+    #[test]
+    fn decoder_panic_is_reported_as_a_crash() {
+        let loading = Loading::spawn_decoder(Arc::new(AtomicU32::new(0)), || {
+            panic!("stub decoder exploded")
+        });
+        while !loading.is_finished() {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        assert!(loading.crashed());
+        let error = loading.join().err().unwrap();
+        assert_eq!(error.to_string(), "Decoder crashed: stub decoder exploded");
+    }
+
+    #[test]
+    fn clean_decode_is_not_a_crash() {
+        let loading = Loading::spawn_decoder(Arc::new(AtomicU32::new(0)), || {
+            anyhow::bail!("ordinary failure")
+        });
+        while !loading.is_finished() {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        assert!(!loading.crashed());
+        assert!(loading.join().is_err());
+    }
+}
@@ -0,0 +1,121 @@
+use std::f32::consts::TAU;
+
+const CROSSFEED_CUTOFF_HZ: f32 = 700.0;
+const CROSSFEED_DELAY_SECS: f32 = 0.0003;
+const CROSSFEED_GAIN: f32 = 0.3;
+
+// Smoothing factor for `y += coefficient * (x - y)` low-passing at `cutoff_hz`.
+pub fn one_pole_coefficient(cutoff_hz: f32, sample_rate: f32) -> f32 {
+    1.0 - (-TAU * cutoff_hz / sample_rate.max(1.0)).exp()
+}
+
+// Bleeds a delayed, low-passed copy of each channel into the other, roughly
+// what each ear would hear from the opposite speaker.
+pub struct Crossfeed {
+    delay: Vec<[f32; 2]>,
+    index: usize,
+    filtered: [f32; 2],
+    coefficient: f32,
+}
+
+impl Crossfeed {
+    pub fn new(sample_rate: u32) -> Self {
+        let sample_rate = sample_rate.max(1) as f32;
+        let delay_frames = ((sample_rate * CROSSFEED_DELAY_SECS).round() as usize).max(1);
+
+        Self {
+            delay: vec![[0.0; 2]; delay_frames],
+            index: 0,
+            filtered: [0.0; 2],
+            coefficient: one_pole_coefficient(CROSSFEED_CUTOFF_HZ, sample_rate),
+        }
+    }
+
+    pub fn process(&mut self, frame: [f32; 2]) -> [f32; 2] {
+        let delayed = std::mem::replace(&mut self.delay[self.index], frame);
+        self.index = (self.index + 1) % self.delay.len();
+
+        for (filtered, delayed) in self.filtered.iter_mut().zip(delayed) {
+            *filtered += self.coefficient * (delayed - *filtered);
+        }
+
+        [
+            (frame[0] + CROSSFEED_GAIN * self.filtered[1]) / (1.0 + CROSSFEED_GAIN),
+            (frame[1] + CROSSFEED_GAIN * self.filtered[0]) / (1.0 + CROSSFEED_GAIN),
+        ]
+    }
+}
+
+// Linear gain ramp so starting or stopping the stream mid-waveform doesn't
+// click. Starts silent and rises once audible.
+#[derive(Default)]
+pub struct Fade {
+    gain: f32,
+}
+
+impl Fade {
+    pub fn next(&mut self, audible: bool, frames: usize) -> f32 {
+        let step = 1.0 / frames.max(1) as f32;
+
+        self.gain = if audible {
+            (self.gain + step).min(1.0)
+        } else {
+            (self.gain - step).max(0.0)
+        };
+
+        self.gain
+    }
+
+    pub fn gain(&self) -> f32 {
+        self.gain
+    }
+}
+
+// Gain at `time` seconds along breakpoints sorted by time, interpolated
+// linearly between them and held flat before the first and after the last.
+// No breakpoints is unity.
+pub fn envelope_gain(points: &[(f64, f32)], time: f64) -> f32 {
+    let after = points.partition_point(|(at, _)| *at <= time);
+
+    match (
+        after.checked_sub(1).map(|index| points[index]),
+        points.get(after).copied(),
+    ) {
+        (None, None) => 1.0,
+        (Some((_, gain)), None) | (None, Some((_, gain))) => gain,
+        (Some((start, from)), Some((end, to))) => {
+            let t = ((time - start) / (end - start)) as f32;
+            from + (to - from) * t
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POINTS: [(f64, f32); 3] = [(1.0, 0.0), (3.0, 1.0), (4.0, 0.5)];
+
+    #[test]
+    fn envelope_hits_each_breakpoint() {
+        for (time, gain) in POINTS {
+            assert_eq!(envelope_gain(&POINTS, time), gain);
+        }
+    }
+
+    #[test]
+    fn envelope_interpolates_between_breakpoints() {
+        assert_eq!(envelope_gain(&POINTS, 2.0), 0.5);
+        assert_eq!(envelope_gain(&POINTS, 2.5), 0.75);
+        assert_eq!(envelope_gain(&POINTS, 3.5), 0.75);
+    }
+
+    #[test]
+    fn envelope_holds_outside_the_breakpoints() {
+        assert_eq!(envelope_gain(&POINTS, 0.0), 0.0);
+        assert_eq!(envelope_gain(&POINTS, -5.0), 0.0);
+        assert_eq!(envelope_gain(&POINTS, 4.5), 0.5);
+        assert_eq!(envelope_gain(&POINTS, 100.0), 0.5);
+        assert_eq!(envelope_gain(&[], 2.0), 1.0);
+    }
+}
@@ -5,3 +5,21 @@ pub mod output;
 pub mod audio;
 
 pub mod vertex;
+
+pub mod meter;
+
+pub mod dsp;
+
+pub mod decode;
+
+pub mod text;
+
+pub mod sink;
+
+pub mod channel;
+
+pub mod peaks;
+
+pub mod view;
+
+pub mod cue;
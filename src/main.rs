@@ -1,5 +1,60 @@
-use oscli::renderer::run;
+use std::path::PathBuf;
+
+use oscli::renderer::{run, RendererConfig, Source, MIN_OSCILLOSCOPE_RANGE};
+
+const USAGE: &str = "usage: oscli [FILE | --input | --input-device NAME] [--window SECONDS] [--no-follow] [--title TITLE] [--size WIDTHxHEIGHT] [--diagnostics]";
 
 fn main() {
-    pollster::block_on(run())
+    env_logger::init();
+
+    let mut diagnostics = false;
+    let mut source = None;
+    let mut renderer_config = RendererConfig::default();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--diagnostics" => diagnostics = true,
+            "--no-follow" => renderer_config.follow_playhead = false,
+            "--input" => source = Some(Source::Input(None)),
+            "--input-device" => match args.next() {
+                Some(name) => source = Some(Source::Input(Some(name))),
+                None => exit_with_usage("--input-device needs a device name"),
+            },
+            "--window" => match args.next().and_then(|value| value.parse::<f32>().ok()) {
+                Some(seconds) if seconds.is_finite() && seconds >= MIN_OSCILLOSCOPE_RANGE => {
+                    renderer_config.oscilloscope_range = seconds
+                }
+                _ => exit_with_usage(&format!(
+                    "--window needs a number of seconds of at least {}",
+                    MIN_OSCILLOSCOPE_RANGE
+                )),
+            },
+            "--title" => match args.next() {
+                Some(title) => renderer_config.title = title,
+                None => exit_with_usage("--title needs a window title"),
+            },
+            "--size" => match args.next().as_deref().and_then(parse_size) {
+                Some(size) => renderer_config.window_size = Some(size),
+                None => exit_with_usage("--size needs a size like 800x200"),
+            },
+            _ if arg.starts_with("--") => exit_with_usage(&format!("unknown option {}", arg)),
+            _ => source = Some(Source::File(PathBuf::from(arg))),
+        }
+    }
+
+    pollster::block_on(run(diagnostics, renderer_config, source))
+}
+
+fn parse_size(value: &str) -> Option<(u32, u32)> {
+    let (width, height) = value.split_once('x')?;
+    match (width.parse().ok()?, height.parse().ok()?) {
+        (0, _) | (_, 0) => None,
+        size => Some(size),
+    }
+}
+
+fn exit_with_usage(message: &str) -> ! {
+    eprintln!("{}\n{}", message, USAGE);
+    std::process::exit(2)
 }
@@ -0,0 +1,101 @@
+use std::time::Duration;
+
+pub struct PeakHold {
+    decay_db_per_sec: f32,
+    level: f32,
+    held: f32,
+}
+
+impl PeakHold {
+    pub fn new(decay_db_per_sec: f32) -> Self {
+        Self {
+            decay_db_per_sec: decay_db_per_sec.max(0.0),
+            level: 0.0,
+            held: 0.0,
+        }
+    }
+
+    pub fn set_decay(&mut self, decay_db_per_sec: f32) {
+        self.decay_db_per_sec = decay_db_per_sec.max(0.0);
+    }
+
+    // Jumps to any new peak immediately, otherwise falls at the configured rate.
+    pub fn update(&mut self, level: f32, dt: Duration) {
+        let decay = 10f32.powf(-self.decay_db_per_sec * dt.as_secs_f32() / 20.0);
+
+        self.level = level;
+        self.held = level.max(self.held * decay);
+    }
+
+    pub fn level(&self) -> f32 {
+        self.level
+    }
+
+    pub fn held(&self) -> f32 {
+        self.held
+    }
+}
+
+pub fn channel_peaks(ring_buffer: &[i32], channels: usize) -> Vec<f32> {
+    let mut peaks = vec![0.0f32; channels.max(1)];
+
+    for frame in ring_buffer.chunks(peaks.len()) {
+        for (peak, sample) in peaks.iter_mut().zip(frame) {
+            *peak = peak.max(sample.unsigned_abs() as f32 / 32768.0);
+        }
+    }
+
+    peaks
+}
+
+// Peaks of the mid `(L + R) / sqrt(2)` and side `(L - R) / sqrt(2)` signals
+// of interleaved stereo.
+pub fn mid_side_peaks(ring_buffer: &[i32]) -> [f32; 2] {
+    let mut peaks = [0.0f32; 2];
+
+    for frame in ring_buffer.chunks_exact(2) {
+        let (left, right) = (frame[0] as f32 / 32768.0, frame[1] as f32 / 32768.0);
+        let mid = (left + right) * std::f32::consts::FRAC_1_SQRT_2;
+        let side = (left - right) * std::f32::consts::FRAC_1_SQRT_2;
+        peaks[0] = peaks[0].max(mid.abs());
+        peaks[1] = peaks[1].max(side.abs());
+    }
+
+    peaks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-5
+    }
+
+    #[test]
+    fn peak_hold_rises_instantly() {
+        let mut meter = PeakHold::new(12.0);
+
+        meter.update(0.8, Duration::from_millis(16));
+
+        assert_eq!(meter.held(), 0.8);
+        assert_eq!(meter.level(), 0.8);
+    }
+
+    #[test]
+    fn peak_hold_falls_at_the_decay_rate() {
+        let mut meter = PeakHold::new(12.0);
+        meter.update(1.0, Duration::ZERO);
+
+        // 12 dB down after one second, 6 dB after another half.
+        meter.update(0.0, Duration::from_secs(1));
+        assert!(close(meter.held(), 10f32.powf(-12.0 / 20.0)));
+        meter.update(0.0, Duration::from_millis(500));
+        assert!(close(meter.held(), 10f32.powf(-18.0 / 20.0)));
+        assert_eq!(meter.level(), 0.0);
+
+        // A new peak above the falling hold takes over at once.
+        meter.update(0.5, Duration::from_millis(10));
+        assert_eq!(meter.held(), 0.5);
+    }
+}
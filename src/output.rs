@@ -1,141 +1,1853 @@
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::Stream;
 use dasp::ring_buffer::Fixed;
-use minimp3::{Decoder, Error, Frame};
+use std::fmt;
 use std::fs::File;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::channel::{self, Receiver, Sender};
+use crate::decode::{decode_range, Decoded, Loading};
+use crate::dsp::{envelope_gain, Crossfeed, Fade};
+use crate::peaks::{PeakFormat, PeakPyramid, Peaks};
+use crate::sink::{CpalSink, OutputSink};
+
+pub const RING_BUFFER_SIZE: usize = 2048;
+pub const PEAK_BLOCK_FRAMES: usize = 1024;
+const DEFAULT_PAUSE_FADE: Duration = Duration::from_millis(5);
+const EVENT_CAPACITY: usize = 64;
+const POSITION_EVENT_INTERVAL: Duration = Duration::from_millis(100);
+pub const MAX_VOLUME: f32 = 2.0;
+pub const MAX_CHANNEL_GAIN: f32 = 4.0;
+pub const MIN_SPEED: f32 = 0.25;
+pub const MAX_SPEED: f32 = 4.0;
+
+#[derive(Debug)]
+pub enum OutputError {
+    TooLong {
+        limit: Duration,
+    },
+    FormatChanged {
+        at: Duration,
+        from: (u32, u16),
+        to: (u32, u16),
+    },
+}
+
+impl fmt::Display for OutputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputError::TooLong { limit } => {
+                write!(f, "File is longer than the {:?} limit", limit)
+            }
+            OutputError::FormatChanged { at, from, to } => write!(
+                f,
+                "Format changes at {:.3}s from {} Hz/{} ch to {} Hz/{} ch",
+                at.as_secs_f64(),
+                from.0,
+                from.1,
+                to.0,
+                to.1
+            ),
+        }
+    }
+}
+
+impl std::error::Error for OutputError {}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PlaybackState {
+    Playing,
+    Paused,
+    Stopped,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum OutputEvent {
+    Loaded {
+        duration: Duration,
+        sample_rate: u32,
+        channels: u16,
+    },
+    StateChanged(PlaybackState),
+    PositionChanged(f64),
+    Finished,
+    Error(String),
+}
+
+// Cloneable control over playback for other threads. Stream setup stays on
+// `Output`.
+#[derive(Clone)]
+pub struct OutputHandle {
+    position: Arc<Mutex<usize>>,
+    length: Arc<AtomicUsize>,
+    format: Arc<Mutex<(u32, u16)>>,
+    audible: Arc<AtomicBool>,
+    open: Arc<AtomicBool>,
+    crossfeed: Arc<AtomicBool>,
+    looping: Arc<AtomicBool>,
+    volume: Arc<AtomicU32>,
+    muted: Arc<AtomicBool>,
+    speed: Arc<AtomicU32>,
+    events: Sender<OutputEvent>,
+}
+
+impl OutputHandle {
+    // As `Output::play`, but only makes the stream audible; if the device was
+    // paused after fading out, the owner's next `Output::poll` restarts it.
+    pub fn play(&self) {
+        self.audible.store(true, Ordering::Relaxed);
+        if self.open.load(Ordering::Relaxed) {
+            self.events
+                .send(OutputEvent::StateChanged(PlaybackState::Playing));
+        }
+    }
+
+    pub fn pause(&self) {
+        self.audible.store(false, Ordering::Relaxed);
+        if self.open.load(Ordering::Relaxed) {
+            self.events
+                .send(OutputEvent::StateChanged(PlaybackState::Paused));
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.audible.load(Ordering::Relaxed)
+    }
+
+    pub fn set_crossfeed(&self, enabled: bool) {
+        self.crossfeed.store(enabled, Ordering::Relaxed);
+    }
+
+    // As `Output::set_volume`.
+    pub fn set_volume(&self, volume: f32) {
+        store_volume(&self.volume, volume);
+    }
+
+    pub fn volume(&self) -> f32 {
+        f32::from_bits(self.volume.load(Ordering::Relaxed))
+    }
+
+    pub fn set_muted(&self, muted: bool) {
+        self.muted.store(muted, Ordering::Relaxed);
+    }
+
+    pub fn toggle_mute(&self) {
+        self.muted.fetch_xor(true, Ordering::Relaxed);
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.muted.load(Ordering::Relaxed)
+    }
+
+    // As `Output::set_speed`.
+    pub fn set_speed(&self, speed: f32) {
+        store_speed(&self.speed, speed);
+    }
+
+    pub fn speed(&self) -> f32 {
+        f32::from_bits(self.speed.load(Ordering::Relaxed))
+    }
+
+    pub fn position_secs(&self) -> f64 {
+        let position = *self.position.lock().unwrap();
+        self.samples_to_secs(position)
+    }
+
+    pub fn duration_secs(&self) -> f64 {
+        self.samples_to_secs(self.length.load(Ordering::Relaxed))
+    }
+
+    pub fn set_position(&self, seconds: f64) {
+        let samples = self.seconds_to_samples(seconds);
+        let length = self.length.load(Ordering::Relaxed);
+        let looping = self.looping.load(Ordering::Relaxed);
+        seek(&self.position, length, looping, &self.events, |_| samples);
+    }
+
+    pub fn forward(&self, seconds: f64) {
+        let samples = self.seconds_to_samples(seconds);
+        let length = self.length.load(Ordering::Relaxed);
+        let looping = self.looping.load(Ordering::Relaxed);
+        seek(&self.position, length, looping, &self.events, |position| {
+            position as i64 + samples
+        });
+    }
+
+    pub fn backward(&self, seconds: f64) {
+        self.forward(-seconds);
+    }
+
+    fn seconds_to_samples(&self, seconds: f64) -> i64 {
+        let (sample_rate, channels) = *self.format.lock().unwrap();
+        (sample_rate as f64 * seconds).round() as i64 * channels as i64
+    }
+
+    fn samples_to_secs(&self, samples: usize) -> f64 {
+        let (sample_rate, channels) = *self.format.lock().unwrap();
+        samples_to_secs(samples, sample_rate, channels)
+    }
+}
+
+// The stream callback's state: reads the decoded buffer at the shared
+// position and applies crossfeed and the pause fade.
+pub struct Playback {
+    rb: Arc<Mutex<Fixed<[i32; RING_BUFFER_SIZE]>>>,
+    buffer: Arc<Vec<i16>>,
+    position: Arc<Mutex<usize>>,
+    crossfeed_enabled: Arc<AtomicBool>,
+    crossfeed: Crossfeed,
+    audible: Arc<AtomicBool>,
+    fade_frames: Arc<AtomicUsize>,
+    fade: Fade,
+    faded_out: Arc<AtomicBool>,
+    normalize: Arc<AtomicBool>,
+    normalize_gain: f32,
+    volume: Arc<AtomicU32>,
+    muted: Arc<AtomicBool>,
+    mute_fade: Fade,
+    channel_gains: Arc<Mutex<Vec<f32>>>,
+    automation: Arc<Mutex<Vec<(f64, f32)>>>,
+    reverse: Arc<AtomicBool>,
+    looping: Arc<AtomicBool>,
+    speed: Arc<AtomicU32>,
+    fraction: f64,
+    events: Sender<OutputEvent>,
+    latency: Arc<AtomicU64>,
+    samples_per_second: f64,
+    report_every: usize,
+    reported: usize,
+    channels: usize,
+    output_channels: usize,
+}
+
+impl Playback {
+    // How far the device's playback trails the end of the last `fill`.
+    pub fn set_latency(&self, latency: Duration) {
+        self.latency
+            .store(latency.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub fn fill(&mut self, data: &mut [f32]) {
+        let buffer = &self.buffer;
+        let channels = self.channels;
+        let mut pos = self.position.lock().unwrap();
+        let mut r_b = self.rb.lock().unwrap();
+        let audible = self.audible.load(Ordering::Relaxed);
+        let fade_frames = self.fade_frames.load(Ordering::Relaxed);
+        let muted = self.muted.load(Ordering::Relaxed);
+        let level = if self.normalize.load(Ordering::Relaxed) {
+            self.normalize_gain
+        } else {
+            1.0
+        } * f32::from_bits(self.volume.load(Ordering::Relaxed));
+        let reverse = self.reverse.load(Ordering::Relaxed);
+        let speed = f32::from_bits(self.speed.load(Ordering::Relaxed)) as f64;
+        let finished = |pos: usize| {
+            if reverse {
+                pos == 0
+            } else {
+                pos >= buffer.len()
+            }
+        };
+        let looping = self.looping.load(Ordering::Relaxed) && !buffer.is_empty();
+        let loop_start = if reverse { buffer.len() } else { 0 };
+        let was_finished = finished(*pos);
+        let channel_gains = self.channel_gains.lock().unwrap();
+        let automation = self.automation.lock().unwrap();
+        for frame in data.chunks_mut(self.output_channels) {
+            // Fully faded out: hold the position until resumed.
+            let gain = self.fade.next(audible, fade_frames);
+            if gain == 0.0 && !audible {
+                frame.fill(0.0);
+                continue;
+            }
+
+            if looping && finished(*pos) {
+                *pos = loop_start;
+            }
+
+            // Muting ramps like pausing but keeps the playhead moving.
+            let unmuted = self.mute_fade.next(!muted, fade_frames);
+            let envelope =
+                unmuted * envelope_gain(&automation, *pos as f64 / self.samples_per_second);
+
+            // Reversed, the playhead walks back one frame at a time and
+            // plays the frame it lands on, stopping at the start. Off unity
+            // speed, output falls between `start` and the next frame along.
+            let at_end = finished(*pos);
+            let start = if reverse {
+                (*pos).min(buffer.len()).saturating_sub(channels)
+            } else {
+                *pos
+            };
+            let frame_at = |steps: usize| {
+                if reverse {
+                    start.checked_sub(steps * channels)
+                } else {
+                    Some(start + steps * channels)
+                }
+            };
+            let raw = |index: Option<usize>, channel: usize| match index {
+                Some(index) if !at_end => buffer.get(index + channel).cloned().unwrap_or(0),
+                _ => 0,
+            };
+            let fraction = self.fraction as f32;
+            // Trims apply before the channels are mapped to the device.
+            let trimmed = |channel: usize| {
+                let from = cpal::Sample::to_f32(&raw(frame_at(0), channel));
+                let to = cpal::Sample::to_f32(&raw(frame_at(1), channel));
+                (from + (to - from) * fraction) * channel_gains.get(channel).unwrap_or(&1.0)
+            };
+
+            // The scope sees every source frame passed over, so its window
+            // stays in track time whatever the speed.
+            self.fraction += speed;
+            let steps = self.fraction.floor();
+            self.fraction -= steps;
+            let steps = steps as usize;
+
+            for step in 0..steps {
+                for channel in 0..channels {
+                    r_b.push(raw(frame_at(step), channel) as i32);
+                }
+            }
+            if reverse {
+                *pos = (*pos).min(buffer.len()).saturating_sub(steps * channels);
+            } else {
+                *pos = (*pos + steps * channels).min(buffer.len());
+            }
+            // Wrap straight away so the end is never observed as finished.
+            if looping && finished(*pos) {
+                *pos = loop_start;
+            }
+
+            // Mono output averages the source; otherwise output channels take
+            // source channels in turn, so mono is duplicated.
+            if frame.len() == 1 && channels > 1 {
+                let sum: f32 = (0..channels).map(trimmed).sum();
+                frame[0] = sum / channels as f32;
+            } else {
+                for (channel, sample) in frame.iter_mut().enumerate() {
+                    *sample = trimmed(channel % channels);
+                }
+            }
+
+            if frame.len() == 2 && self.crossfeed_enabled.load(Ordering::Relaxed) {
+                let [left, right] = self.crossfeed.process([frame[0], frame[1]]);
+                frame[0] = left;
+                frame[1] = right;
+            }
+
+            for sample in frame.iter_mut() {
+                *sample *= gain * envelope * level;
+            }
+        }
+        // Tells `Output::poll` the device can be paused.
+        self.faded_out
+            .store(!audible && self.fade.gain() == 0.0, Ordering::Relaxed);
+
+        if *pos < self.reported || *pos >= self.reported + self.report_every {
+            self.reported = *pos;
+            self.events.send(OutputEvent::PositionChanged(
+                *pos as f64 / self.samples_per_second,
+            ));
+        }
+
+        if !was_finished && finished(*pos) {
+            self.events.send(OutputEvent::Finished);
+        }
+    }
+}
+
+// Moves the shared position to `target(current)`, clamped to the track. Seeking
+// onto the end finishes the track rather than leaving it playing silence, or
+// wraps to the start when looping.
+fn seek(
+    position: &Mutex<usize>,
+    length: usize,
+    looping: bool,
+    events: &Sender<OutputEvent>,
+    target: impl FnOnce(usize) -> i64,
+) {
+    let mut position = position.lock().unwrap();
+    let was_finished = *position >= length;
+
+    *position = target(*position).clamp(0, length as i64) as usize;
+    if looping && *position >= length {
+        *position = 0;
+    }
+
+    if !was_finished && *position >= length {
+        events.send(OutputEvent::Finished);
+    }
+}
+
+// Frame ranges of the track's `columns` equal slices, for thumbnails.
+fn equal_slices(frames: usize, columns: usize) -> impl Fn(usize) -> Range<usize> + Copy + Sync {
+    move |column| column * frames / columns..(column + 1) * frames / columns
+}
+
+// Frame ranges of consecutive `PEAK_BLOCK_FRAMES` blocks, the last one short,
+// so block `b` always starts at frame `b * PEAK_BLOCK_FRAMES`.
+fn fixed_blocks(frames: usize) -> impl Fn(usize) -> Range<usize> + Copy + Sync {
+    move |block| block * PEAK_BLOCK_FRAMES..((block + 1) * PEAK_BLOCK_FRAMES).min(frames)
+}
+
+// Min/max of the frames `bounds` gives each block in `range`, for one channel
+// or across all of them.
+fn range_peaks(
+    buffer: &[i16],
+    channels: usize,
+    range: Range<usize>,
+    bounds: impl Fn(usize) -> Range<usize>,
+    channel: Option<usize>,
+) -> Vec<(f32, f32)> {
+    range
+        .map(|block| {
+            let frames = bounds(block);
+
+            buffer[frames.start * channels..frames.end * channels]
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| channel.is_none_or(|channel| index % channels == channel))
+                .map(|(_, sample)| *sample as f32 / 32768.0)
+                .fold(None, |peak: Option<(f32, f32)>, value| match peak {
+                    Some((min, max)) => Some((min.min(value), max.max(value))),
+                    None => Some((value, value)),
+                })
+                .unwrap_or((0.0, 0.0))
+        })
+        .collect()
+}
+
+// RMS of the same blocks as `range_peaks`.
+fn range_rms(
+    buffer: &[i16],
+    channels: usize,
+    range: Range<usize>,
+    bounds: impl Fn(usize) -> Range<usize>,
+    channel: Option<usize>,
+) -> Vec<f32> {
+    range
+        .map(|block| {
+            let frames = bounds(block);
+
+            let (sum, count) = buffer[frames.start * channels..frames.end * channels]
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| channel.is_none_or(|channel| index % channels == channel))
+                .map(|(_, sample)| *sample as f32 / 32768.0)
+                .fold((0.0, 0), |(sum, count), value| {
+                    (sum + value * value, count + 1)
+                });
+
+            if count == 0 {
+                0.0
+            } else {
+                (sum / count as f32).sqrt()
+            }
+        })
+        .collect()
+}
+
+fn store_volume(atomic: &AtomicU32, volume: f32) {
+    if !volume.is_nan() {
+        atomic.store(volume.clamp(0.0, MAX_VOLUME).to_bits(), Ordering::Relaxed);
+    }
+}
+
+fn store_speed(atomic: &AtomicU32, speed: f32) {
+    if !speed.is_nan() {
+        atomic.store(
+            speed.clamp(MIN_SPEED, MAX_SPEED).to_bits(),
+            Ordering::Relaxed,
+        );
+    }
+}
+
+// Interleaved sample counts are `channels` samples per frame.
+fn samples_to_secs(samples: usize, sample_rate: u32, channels: u16) -> f64 {
+    samples as f64 / (sample_rate.max(1) as f64 * channels.max(1) as f64)
+}
+
+fn normalize_gain(buffer: &[i16]) -> f32 {
+    let peak = buffer
+        .iter()
+        .map(|sample| sample.unsigned_abs())
+        .max()
+        .unwrap_or(0);
+
+    if peak == 0 {
+        1.0
+    } else {
+        i16::MAX as f32 / peak as f32
+    }
+}
 
 pub struct Output {
     pub buffer: Arc<Vec<i16>>,
     pub sample_rate: cpal::SampleRate,
     pub channels: cpal::ChannelCount,
-    pub stream: Option<Stream>,
     pub position: Arc<Mutex<usize>>,
-    rb: Arc<Mutex<Fixed<[i32; 2048]>>>,
+    rb: Arc<Mutex<Fixed<[i32; RING_BUFFER_SIZE]>>>,
+    crossfeed: Arc<AtomicBool>,
+    normalize: Arc<AtomicBool>,
+    volume: Arc<AtomicU32>,
+    muted: Arc<AtomicBool>,
+    channel_gains: Arc<Mutex<Vec<f32>>>,
+    automation: Arc<Mutex<Vec<(f64, f32)>>>,
+    reverse: Arc<AtomicBool>,
+    looping: Arc<AtomicBool>,
+    speed: Arc<AtomicU32>,
+    audible: Arc<AtomicBool>,
+    open: Arc<AtomicBool>,
+    running: bool,
+    faded_out: Arc<AtomicBool>,
+    format: Arc<Mutex<(u32, u16)>>,
+    length: Arc<AtomicUsize>,
+    latency: Arc<AtomicU64>,
+    pause_fade: Duration,
+    fade_frames: Arc<AtomicUsize>,
+    events: Sender<OutputEvent>,
+    receiver: Option<Receiver<OutputEvent>>,
+    max_duration: Option<Duration>,
+    stream_config: Option<cpal::StreamConfig>,
+    buffer_frames: Option<u32>,
+    output_channels: Option<u16>,
+    sink: Box<dyn OutputSink>,
+    input: Option<cpal::Stream>,
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Output {
     pub fn new() -> Self {
-        let rb = Arc::new(Mutex::new(Fixed::from([0; 2048])));
+        let rb = Arc::new(Mutex::new(Fixed::from([0; RING_BUFFER_SIZE])));
+        let (events, receiver) = channel::bounded(EVENT_CAPACITY);
 
         Self {
             buffer: Arc::new(Vec::new()),
             sample_rate: cpal::SampleRate(44100),
             channels: 2,
-            stream: None,
             position: Arc::new(Mutex::new(0)),
             rb,
+            crossfeed: Arc::new(AtomicBool::new(false)),
+            normalize: Arc::new(AtomicBool::new(false)),
+            volume: Arc::new(AtomicU32::new(1.0f32.to_bits())),
+            channel_gains: Arc::new(Mutex::new(vec![1.0; 2])),
+            muted: Arc::new(AtomicBool::new(false)),
+            automation: Arc::new(Mutex::new(vec![])),
+            reverse: Arc::new(AtomicBool::new(false)),
+            looping: Arc::new(AtomicBool::new(false)),
+            speed: Arc::new(AtomicU32::new(1.0f32.to_bits())),
+            audible: Arc::new(AtomicBool::new(false)),
+            open: Arc::new(AtomicBool::new(false)),
+            running: false,
+            faded_out: Arc::new(AtomicBool::new(false)),
+            format: Arc::new(Mutex::new((44100, 2))),
+            length: Arc::new(AtomicUsize::new(0)),
+            latency: Arc::new(AtomicU64::new(0)),
+            pause_fade: DEFAULT_PAUSE_FADE,
+            fade_frames: Arc::new(AtomicUsize::new(0)),
+            events,
+            receiver: Some(receiver),
+            max_duration: None,
+            stream_config: None,
+            buffer_frames: None,
+            output_channels: None,
+            sink: Box::new(CpalSink::default()),
+            input: None,
         }
     }
 
-    pub fn load_file(&mut self, file: File) {
-        let mut decoder = Decoder::new(file);
-        let mut buffer = Vec::new();
-        let mut sample_rate = cpal::SampleRate(0);
-        let mut channels: cpal::ChannelCount = 1;
-
-        loop {
-            match decoder.next_frame() {
-                Ok(Frame {
-                    mut data,
-                    sample_rate: rate,
-                    channels: ch,
-                    ..
-                }) => {
-                    sample_rate = cpal::SampleRate(rate as u32);
-                    channels = ch as cpal::ChannelCount;
-                    buffer.append(&mut data);
-                }
-                Err(Error::Eof) => break,
-                Err(e) => panic!("{:?}", e),
+    // Replaces the audio device, e.g. with a `RecordingSink` to run without
+    // hardware. Takes effect from the next load.
+    pub fn set_output(&mut self, sink: Box<dyn OutputSink>) {
+        self.close_sink();
+        self.sink = sink;
+    }
+
+    fn close_sink(&mut self) {
+        self.sink.close();
+        self.open.store(false, Ordering::Relaxed);
+        self.running = false;
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        self.sink.is_open()
+    }
+
+    pub fn with_max_duration(mut self, max_duration: Duration) -> Self {
+        self.max_duration = Some(max_duration);
+        self
+    }
+
+    // Load, playback state, throttled position and end-of-track events. There
+    // is a single receiver, so this only returns it once. At most
+    // `EVENT_CAPACITY` are kept: when it isn't drained the oldest are dropped,
+    // so playback never blocks and the latest state is always there.
+    pub fn events(&mut self) -> Option<Receiver<OutputEvent>> {
+        self.receiver.take()
+    }
+
+    pub fn handle(&self) -> OutputHandle {
+        OutputHandle {
+            position: self.position.clone(),
+            length: self.length.clone(),
+            format: self.format.clone(),
+            audible: self.audible.clone(),
+            open: self.open.clone(),
+            crossfeed: self.crossfeed.clone(),
+            looping: self.looping.clone(),
+            volume: self.volume.clone(),
+            muted: self.muted.clone(),
+            speed: self.speed.clone(),
+            events: self.events.clone(),
+        }
+    }
+
+    fn publish(&self, event: OutputEvent) {
+        self.events.send(event);
+    }
+
+    pub fn load_file(&mut self, file: File) -> anyhow::Result<()> {
+        self.load_file_range(file, 0.0, f64::INFINITY)
+    }
+
+    // Keeps only the audio between `start_secs` and `end_secs`; positions are
+    // then relative to the start of the range.
+    pub fn load_file_range(
+        &mut self,
+        file: File,
+        start_secs: f64,
+        end_secs: f64,
+    ) -> anyhow::Result<()> {
+        let start_secs = start_secs.max(0.0);
+        if start_secs >= end_secs {
+            anyhow::bail!("Invalid range: {}s..{}s", start_secs, end_secs);
+        }
+
+        let decoded = decode_range(file, start_secs, end_secs, self.max_duration)?;
+
+        self.install(decoded)
+    }
+
+    // Decodes on a background thread; pass the handle to `finish_load` once
+    // it reports finished.
+    pub fn start_load(&self, file: File) -> Loading {
+        Loading::spawn(file, self.max_duration)
+    }
+
+    pub fn finish_load(&mut self, loading: Loading) -> anyhow::Result<()> {
+        let result = loading.join().and_then(|decoded| self.install(decoded));
+        if let Err(e) = &result {
+            self.publish(OutputEvent::Error(e.to_string()));
+        }
+
+        result
+    }
+
+    // Decodes `path` for analysis only: peaks, thumbnails and exports work,
+    // but no audio device is ever opened, so it can't be played.
+    pub fn from_path_no_audio<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let mut output = Self::new();
+        let decoded = decode_range(File::open(path)?, 0.0, f64::INFINITY, None)?;
+        output.set_track(
+            Arc::new(decoded.samples),
+            decoded.sample_rate,
+            decoded.channels,
+        );
+
+        Ok(output)
+    }
+
+    // Nothing changes until the device has accepted the new track, so a file
+    // it can't play leaves the current one loaded and playing.
+    fn install(&mut self, decoded: Decoded) -> anyhow::Result<()> {
+        let was_open = self.sink.is_open();
+        let buffer = Arc::new(decoded.samples);
+
+        if let Err(e) = self.open_sink(buffer.clone(), decoded.sample_rate, decoded.channels) {
+            if was_open {
+                self.reopen();
             }
+            return Err(e);
+        }
+
+        self.input = None;
+        self.set_track(buffer, decoded.sample_rate, decoded.channels);
+
+        Ok(())
+    }
+
+    // Puts the current track back on the device after a failed open, which
+    // may already have closed it.
+    fn reopen(&mut self) {
+        if let Err(e) = self.open_sink(self.buffer.clone(), self.sample_rate, self.channels) {
+            log::error!("Failed to reopen the current track: {}", e);
         }
+    }
+
+    // The stream starts straight away and `audible` alone decides whether
+    // it's heard, so handles on other threads can start and stop playback
+    // without touching the device. `poll` pauses it once a pause has faded
+    // out.
+
+    fn open_sink(
+        &mut self,
+        buffer: Arc<Vec<i16>>,
+        sample_rate: cpal::SampleRate,
+        channels: cpal::ChannelCount,
+    ) -> anyhow::Result<()> {
+        let samples_per_second = sample_rate.0 as f64 * channels as f64;
+        let output_channels = self.output_channels.unwrap_or(channels).max(1);
 
-        self.buffer = Arc::new(buffer);
+        let playback = Playback {
+            rb: self.rb.clone(),
+            normalize_gain: normalize_gain(&buffer),
+            buffer,
+            position: self.position.clone(),
+            crossfeed_enabled: self.crossfeed.clone(),
+            crossfeed: Crossfeed::new(sample_rate.0),
+            audible: self.audible.clone(),
+            fade_frames: self.fade_frames.clone(),
+            fade: Fade::default(),
+            faded_out: self.faded_out.clone(),
+            normalize: self.normalize.clone(),
+            volume: self.volume.clone(),
+            muted: self.muted.clone(),
+            mute_fade: Fade::default(),
+            channel_gains: self.channel_gains.clone(),
+            automation: self.automation.clone(),
+            reverse: self.reverse.clone(),
+            looping: self.looping.clone(),
+            speed: self.speed.clone(),
+            fraction: 0.0,
+            events: self.events.clone(),
+            latency: self.latency.clone(),
+            samples_per_second,
+            report_every: (POSITION_EVENT_INTERVAL.as_secs_f64() * samples_per_second) as usize,
+            reported: 0,
+            channels: channels as usize,
+            output_channels: output_channels as usize,
+        };
+
+        let opened = self
+            .sink
+            .open(sample_rate, output_channels, self.buffer_frames, playback);
+        self.open.store(self.sink.is_open(), Ordering::Relaxed);
+        self.stream_config = Some(opened?);
+        self.sink.play();
+        self.running = true;
+
+        Ok(())
+    }
+
+    // Swaps in a decoded track without touching the audio device.
+    fn set_track(
+        &mut self,
+        buffer: Arc<Vec<i16>>,
+        sample_rate: cpal::SampleRate,
+        channels: cpal::ChannelCount,
+    ) {
+        self.buffer = buffer;
         self.sample_rate = sample_rate;
         self.channels = channels;
+        *self.format.lock().unwrap() = (self.sample_rate.0, self.channels);
+        self.length.store(self.buffer.len(), Ordering::Relaxed);
+        *self.position.lock().unwrap() = 0;
+        self.channel_gains
+            .lock()
+            .unwrap()
+            .resize(self.channels as usize, 1.0);
+        self.set_pause_fade(self.pause_fade);
+
+        self.publish(OutputEvent::Loaded {
+            duration: Duration::from_secs_f64(self.duration_secs()),
+            sample_rate: self.sample_rate.0,
+            channels: self.channels,
+        });
+    }
+
+    // Scopes a capture device (the default one when `name` is `None`) instead
+    // of a file. Captured samples feed the same ring buffer the renderer
+    // reads; nothing is played back and seeking does nothing.
+    pub fn open_input(&mut self, name: Option<&str>) -> anyhow::Result<()> {
+        self.input = None;
+        self.close_sink();
 
         let host = cpal::default_host();
+        let device = match name {
+            Some(name) => host
+                .input_devices()?
+                .find(|device| device.name().ok().as_deref() == Some(name)),
+            None => host.default_input_device(),
+        }
+        .ok_or_else(|| anyhow::anyhow!("No input device {}", name.unwrap_or("available")))?;
 
-        let device = host
-            .default_output_device()
-            .expect("no output device available");
-
-        let mut supported_configs_range = device
-            .supported_output_configs()
-            .expect("error while querying configs");
-
-        let supported_config = supported_configs_range
-            .find(|range| {
-                range.sample_format() == cpal::SampleFormat::F32
-                    && range.max_sample_rate() >= self.sample_rate
-                    && range.min_sample_rate() <= self.sample_rate
-                    && range.channels() == self.channels
-            })
-            .expect("Could not find supported audio config")
-            .with_sample_rate(self.sample_rate);
+        let supported_config = device.default_input_config()?;
+        let config: cpal::StreamConfig = supported_config.config();
+
+        self.buffer = Arc::new(Vec::new());
+        self.sample_rate = config.sample_rate;
+        self.channels = config.channels;
+        *self.format.lock().unwrap() = (self.sample_rate.0, self.channels);
+        self.length.store(0, Ordering::Relaxed);
+        *self.position.lock().unwrap() = 0;
+        self.stream_config = Some(config.clone());
+
+        let stream = match supported_config.sample_format() {
+            cpal::SampleFormat::F32 => self.build_input::<f32>(&device, &config)?,
+            cpal::SampleFormat::I16 => self.build_input::<i16>(&device, &config)?,
+            cpal::SampleFormat::U16 => self.build_input::<u16>(&device, &config)?,
+        };
+        stream.play()?;
+        self.input = Some(stream);
 
+        Ok(())
+    }
+
+    fn build_input<T: cpal::Sample>(
+        &self,
+        device: &cpal::Device,
+        config: &cpal::StreamConfig,
+    ) -> anyhow::Result<cpal::Stream> {
         let rb = self.rb.clone();
-        let buffer = self.buffer.clone();
         let position = self.position.clone();
 
-        self.stream = Some(
-            device
-                .build_output_stream(
-                    &supported_config.into(),
-                    move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-                        let mut pos = position.lock().unwrap();
-                        let mut r_b = rb.lock().unwrap();
-                        for sample in data.iter_mut() {
-                            let value = if *pos < buffer.len() { buffer[*pos] } else { 0 };
-                            *sample = cpal::Sample::from(&value);
-
-                            let mut n = r_b.clone();
-                            n.push(value as i32);
-                            *r_b = n;
-
-                            *pos += 1;
-                        }
-                    },
-                    move |_err| panic!("ERROR"),
-                )
-                .expect("Building output stream failed"),
-        );
+        let stream = device.build_input_stream(
+            config,
+            move |data: &[T], _: &cpal::InputCallbackInfo| {
+                let mut pos = position.lock().unwrap();
+                let mut r_b = rb.lock().unwrap();
+                for sample in data {
+                    r_b.push(sample.to_i16() as i32);
+                }
+                *pos += data.len();
+            },
+            move |err| log::error!("Input stream error: {}", err),
+        )?;
+
+        Ok(stream)
+    }
+
+    pub fn is_input(&self) -> bool {
+        self.input.is_some()
+    }
+
+    // Whether positions mean anything: false for live input.
+    pub fn is_seekable(&self) -> bool {
+        self.input.is_none()
+    }
+
+    pub fn device_name(&self) -> String {
+        cpal::default_host()
+            .default_output_device()
+            .and_then(|device| device.name().ok())
+            .unwrap_or_else(|| String::from("no output device"))
+    }
+
+    pub fn device_config(&self) -> Option<cpal::SupportedStreamConfig> {
+        cpal::default_host()
+            .default_output_device()?
+            .default_output_config()
+            .ok()
+    }
+
+    pub fn set_crossfeed(&mut self, enabled: bool) {
+        self.crossfeed.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn crossfeed(&self) -> bool {
+        self.crossfeed.load(Ordering::Relaxed)
+    }
+
+    // Scales playback so the loudest sample of the track reaches full scale.
+    // Only affects what is heard; the scope and meters still see the file's
+    // own levels, and display scaling is the renderer's auto-fit.
+    pub fn set_normalize(&mut self, enabled: bool) {
+        self.normalize.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn normalize(&self) -> bool {
+        self.normalize.load(Ordering::Relaxed)
+    }
+
+    // Linear gain on what is heard, 0 to `MAX_VOLUME`. Applies immediately.
+    pub fn set_volume(&mut self, volume: f32) {
+        store_volume(&self.volume, volume);
+    }
+
+    pub fn volume(&self) -> f32 {
+        f32::from_bits(self.volume.load(Ordering::Relaxed))
+    }
+
+    // Silences playback without touching the volume or stopping the
+    // playhead. Ramps over the pause fade to avoid clicks.
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted.store(muted, Ordering::Relaxed);
+    }
+
+    pub fn toggle_mute(&mut self) {
+        self.muted.fetch_xor(true, Ordering::Relaxed);
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.muted.load(Ordering::Relaxed)
+    }
+
+    // Trims one channel of the loaded file before it is mixed to the device,
+    // 0 to `MAX_CHANNEL_GAIN`. Trims are kept across loads with the same
+    // channel count; new channels start at unity.
+    pub fn set_channel_gain(&mut self, channel: usize, gain: f32) -> anyhow::Result<()> {
+        let mut gains = self.channel_gains.lock().unwrap();
+        if channel >= gains.len() {
+            anyhow::bail!(
+                "Channel {} out of range for {} channels",
+                channel,
+                gains.len()
+            );
+        }
+        if gain.is_nan() {
+            anyhow::bail!("Channel gain is NaN");
+        }
+
+        gains[channel] = gain.clamp(0.0, MAX_CHANNEL_GAIN);
+
+        Ok(())
+    }
+
+    pub fn channel_gain(&self, channel: usize) -> Option<f32> {
+        self.channel_gains.lock().unwrap().get(channel).cloned()
+    }
+
+    // Gain envelope over the track as (seconds, gain) breakpoints, linear in
+    // between and held flat beyond the ends, e.g. `[(170.0, 1.0), (180.0, 0.0)]`
+    // fades out the last ten seconds of a three-minute track. Applied with
+    // volume and normalize; an empty list turns it off.
+    pub fn set_gain_automation(&mut self, mut points: Vec<(f64, f32)>) {
+        points.retain(|(time, gain)| time.is_finite() && !gain.is_nan());
+        for (_, gain) in points.iter_mut() {
+            *gain = gain.clamp(0.0, MAX_VOLUME);
+        }
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        *self.automation.lock().unwrap() = points;
+    }
+
+    // Plays backwards from the current position towards the start, which
+    // then counts as the end of the track. Applies immediately.
+    pub fn set_reverse(&mut self, enabled: bool) {
+        self.reverse.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn reverse(&self) -> bool {
+        self.reverse.load(Ordering::Relaxed)
+    }
+
+    // Plays `speed` times as fast by linear interpolation between frames;
+    // pitch moves with it. Positions stay in track time.
+    pub fn set_speed(&mut self, speed: f32) {
+        store_speed(&self.speed, speed);
+    }
+
+    pub fn speed(&self) -> f32 {
+        f32::from_bits(self.speed.load(Ordering::Relaxed))
+    }
+
+    // Jumps back to the start on reaching the end instead of finishing.
+    // Turning it off mid-track lets the track finish normally.
+    pub fn set_loop(&mut self, enabled: bool) {
+        self.looping.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_looping(&self) -> bool {
+        self.looping.load(Ordering::Relaxed)
+    }
+
+    // Requests a fixed device buffer from the next load on; `None` or a size
+    // the device doesn't support uses its default. `stream_config` reports
+    // what was granted.
+    pub fn set_output_buffer_frames(&mut self, frames: Option<u32>) {
+        self.buffer_frames = frames;
+    }
+
+    // Opens the device with this many channels from the next load on,
+    // whatever the file has: mono is duplicated and multichannel averaged
+    // down to mono. `None` follows the file.
+    pub fn set_output_channels(&mut self, channels: Option<u16>) {
+        self.output_channels = channels.filter(|channels| *channels > 0);
+    }
+
+    pub fn stream_config(&self) -> Option<cpal::StreamConfig> {
+        self.stream_config.clone()
     }
 
     pub fn play(&mut self) {
-        if let Some(ref stream) = self.stream {
-            stream.play().unwrap();
+        self.audible.store(true, Ordering::Relaxed);
+        if self.sink.is_open() {
+            self.sink.play();
+            self.running = true;
+            self.publish(OutputEvent::StateChanged(PlaybackState::Playing));
+        }
+    }
+
+    // Pauses the device once a pause has fully faded out, so a paused track
+    // costs nothing, and restarts it when a handle resumes playback. Call it
+    // regularly, e.g. once a frame; until then the stream just plays silence.
+    pub fn poll(&mut self) {
+        if !self.sink.is_open() {
             return;
         }
+
+        let audible = self.is_playing();
+        if audible && !self.running {
+            self.sink.play();
+            self.running = true;
+        } else if !audible && self.running && self.faded_out.load(Ordering::Relaxed) {
+            self.sink.pause();
+            self.running = false;
+        }
+    }
+
+    // Playing or paused as last requested; stays true at the end of a track.
+    pub fn is_playing(&self) -> bool {
+        self.audible.load(Ordering::Relaxed)
+    }
+
+    // Seconds of audio handed to the device so far.
+    pub fn position_secs(&self) -> f64 {
+        let position = *self.position.lock().unwrap();
+        samples_to_secs(position, self.sample_rate.0, self.channels)
+    }
+
+    pub fn duration_secs(&self) -> f64 {
+        samples_to_secs(self.buffer.len(), self.sample_rate.0, self.channels)
+    }
+
+    // `position_secs` less the device's output latency, i.e. what is
+    // actually being heard.
+    pub fn audible_position_secs(&self) -> f64 {
+        (self.position_secs() - self.latency().as_secs_f64()).max(0.0)
+    }
+
+    pub fn latency(&self) -> Duration {
+        Duration::from_nanos(self.latency.load(Ordering::Relaxed))
+    }
+
+    pub fn is_finished(&self) -> bool {
+        let position = *self.position.lock().unwrap();
+        !self.is_input()
+            && if self.reverse() {
+                position == 0
+            } else {
+                position >= self.buffer.len()
+            }
     }
 
     pub fn set_position(&mut self, seconds: f64) {
-        let mut position = self.position.lock().unwrap();
-        *position = self.seconds_to_samples(seconds).max(0) as usize;
+        if !self.is_seekable() {
+            return;
+        }
+        let samples = self.seconds_to_samples(seconds);
+        seek(
+            &self.position,
+            self.buffer.len(),
+            self.is_looping(),
+            &self.events,
+            |_| samples,
+        );
     }
 
+    // Fades out over the pause fade and holds the position; `play` fades back
+    // in. The device keeps running until `poll` sees the fade finish, so the
+    // fade is always rendered and the caller never waits for it.
     pub fn pause(&mut self) {
-        if let Some(ref stream) = self.stream {
-            stream.pause().unwrap()
+        self.audible.store(false, Ordering::Relaxed);
+        if self.sink.is_open() {
+            self.publish(OutputEvent::StateChanged(PlaybackState::Paused));
         }
     }
 
+    // Drops the stream without waiting for the fade; a new load is needed to
+    // play again.
+    pub fn stop(&mut self) {
+        self.input = None;
+        self.pause();
+        if self.sink.is_open() {
+            self.close_sink();
+            self.publish(OutputEvent::StateChanged(PlaybackState::Stopped));
+        }
+    }
+
+    pub fn set_pause_fade(&mut self, fade: Duration) {
+        self.pause_fade = fade;
+        let frames = (fade.as_secs_f64() * self.sample_rate.0 as f64).round() as usize;
+        self.fade_frames.store(frames, Ordering::Relaxed);
+    }
+
+    pub fn pause_fade(&self) -> Duration {
+        self.pause_fade
+    }
+
     pub fn forward(&mut self, seconds: f64) {
+        if !self.is_seekable() {
+            return;
+        }
         let number_of_samples = self.seconds_to_samples(seconds);
-        let mut position = self.position.lock().unwrap();
-        *position = (*position as i32 + number_of_samples).max(0) as usize;
+        seek(
+            &self.position,
+            self.buffer.len(),
+            self.is_looping(),
+            &self.events,
+            |position| position as i64 + number_of_samples,
+        );
+    }
+
+    pub fn backward(&mut self, seconds: f64) {
+        self.forward(-seconds);
     }
 
-    fn seconds_to_samples(&self, seconds: f64) -> i32 {
-        (self.sample_rate.0 as f64 * seconds) as i32 * self.channels as i32
+    pub fn export_loop_wav<P: AsRef<Path>>(
+        &self,
+        start_secs: f64,
+        end_secs: f64,
+        path: P,
+    ) -> anyhow::Result<()> {
+        let channels = self.channels as usize;
+        let total_frames = self.buffer.len() / channels;
+        let start = (self.seconds_to_frames(start_secs).max(0) as usize).min(total_frames);
+        let end = (self.seconds_to_frames(end_secs).max(0) as usize).min(total_frames);
+
+        if start >= end {
+            anyhow::bail!("Loop region is empty: {}s..{}s", start_secs, end_secs);
+        }
+
+        let spec = hound::WavSpec {
+            channels: self.channels,
+            sample_rate: self.sample_rate.0,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+
+        let mut writer = hound::WavWriter::create(path, spec)?;
+        for sample in &self.buffer[start * channels..end * channels] {
+            writer.write_sample(*sample)?;
+        }
+        writer.finalize()?;
+
+        Ok(())
+    }
+
+    // Renders the whole track as white min/max bars on a transparent
+    // background: `width` x `height` RGBA pixels, row-major from the top.
+    pub fn thumbnail(&self, width: usize, height: usize) -> Vec<u8> {
+        self.render_thumbnail(width, height, false, false)
+    }
+
+    // As `thumbnail`, with the playhead drawn as a red column on top.
+    pub fn thumbnail_with_playhead(&self, width: usize, height: usize) -> Vec<u8> {
+        self.render_thumbnail(width, height, true, false)
+    }
+
+    // As `thumbnail`, with each column's RMS level drawn as a grey band
+    // inside the min/max envelope.
+    pub fn thumbnail_with_rms(&self, width: usize, height: usize) -> Vec<u8> {
+        self.render_thumbnail(width, height, false, true)
+    }
+
+    fn render_thumbnail(&self, width: usize, height: usize, playhead: bool, rms: bool) -> Vec<u8> {
+        let mut pixels = vec![0; width * height * 4];
+        if width == 0 || height == 0 {
+            return pixels;
+        }
+
+        let row = |value: f32| ((1.0 - value) / 2.0 * (height - 1) as f32).round() as usize;
+
+        let frames = self.buffer.len() / self.channels.max(1) as usize;
+        let columns = equal_slices(frames, width);
+
+        for (x, (min, max)) in self
+            .block_peaks(width, columns, None)
+            .into_iter()
+            .enumerate()
+        {
+            for y in row(max)..=row(min) {
+                let offset = (y * width + x) * 4;
+                pixels[offset..offset + 4].copy_from_slice(&[255; 4]);
+            }
+        }
+
+        if rms {
+            let channels = self.channels.max(1) as usize;
+            for (x, level) in range_rms(&self.buffer, channels, 0..width, columns, None)
+                .into_iter()
+                .enumerate()
+            {
+                for y in row(level)..=row(-level) {
+                    let offset = (y * width + x) * 4;
+                    pixels[offset..offset + 4].copy_from_slice(&[128, 128, 128, 255]);
+                }
+            }
+        }
+
+        if playhead && !self.buffer.is_empty() {
+            let position = *self.position.lock().unwrap();
+            let x = (position * width / self.buffer.len()).min(width - 1);
+            for y in 0..height {
+                let offset = (y * width + x) * 4;
+                pixels[offset..offset + 4].copy_from_slice(&[255, 0, 0, 255]);
+            }
+        }
+
+        pixels
+    }
+
+    // Min/max envelope of one channel, one pair per `PEAK_BLOCK_FRAMES`
+    // frames from the start (the last block may be short), in -1..1.
+    pub fn channel_peaks(&self, channel: usize) -> anyhow::Result<Vec<(f32, f32)>> {
+        if channel >= self.channels as usize {
+            anyhow::bail!(
+                "Channel {} out of range for {} channels",
+                channel,
+                self.channels
+            );
+        }
+
+        let frames = self.buffer.len() / self.channels as usize;
+        let blocks = frames.div_ceil(PEAK_BLOCK_FRAMES);
+
+        Ok(self.block_peaks(blocks, fixed_blocks(frames), Some(channel)))
+    }
+
+    // `peaks` plus every coarser power-of-two level, for picking one to match
+    // a zoom.
+    pub fn peak_pyramid(&self) -> PeakPyramid {
+        PeakPyramid::new(self.peaks())
+    }
+
+    // RMS level of one channel per `PEAK_BLOCK_FRAMES` frames, in 0..1,
+    // aligned with `channel_peaks`.
+    pub fn channel_rms(&self, channel: usize) -> anyhow::Result<Vec<f32>> {
+        if channel >= self.channels as usize {
+            anyhow::bail!(
+                "Channel {} out of range for {} channels",
+                channel,
+                self.channels
+            );
+        }
+
+        let channels = self.channels as usize;
+        let frames = self.buffer.len() / channels;
+        let blocks = frames.div_ceil(PEAK_BLOCK_FRAMES);
+
+        Ok(range_rms(
+            &self.buffer,
+            channels,
+            0..blocks,
+            fixed_blocks(frames),
+            Some(channel),
+        ))
+    }
+
+    // Every channel's envelope at `PEAK_BLOCK_FRAMES` per peak.
+    pub fn peaks(&self) -> Peaks {
+        let channels = (0..self.channels as usize)
+            .map(|channel| self.channel_peaks(channel).unwrap_or_default())
+            .collect::<Vec<_>>();
+        let blocks = channels.first().map_or(0, Vec::len);
+
+        Peaks {
+            sample_rate: self.sample_rate.0,
+            channels: self.channels,
+            frames_per_peak: PEAK_BLOCK_FRAMES as u32,
+            peaks: (0..blocks)
+                .flat_map(|block| channels.iter().map(move |peaks| peaks[block]))
+                .collect(),
+        }
+    }
+
+    pub fn save_peaks_bin<P: AsRef<Path>>(
+        &self,
+        path: P,
+        format: PeakFormat,
+    ) -> anyhow::Result<()> {
+        self.peaks().save(path, format)
+    }
+
+    // Min and max of `blocks` blocks of the decoded audio, block `b` covering
+    // the frames `bounds(b)`, for one channel or across all of them, in -1..1.
+    // Blocks are reduced on all cores, each thread taking a contiguous run so
+    // the result is in the same order as a serial pass.
+    fn block_peaks(
+        &self,
+        blocks: usize,
+        bounds: impl Fn(usize) -> Range<usize> + Copy + Send,
+        channel: Option<usize>,
+    ) -> Vec<(f32, f32)> {
+        let buffer = &self.buffer[..];
+        let channels = self.channels.max(1) as usize;
+        let threads = thread::available_parallelism().map_or(1, |threads| threads.get());
+        let per_thread = blocks.div_ceil(threads).max(1);
+
+        thread::scope(|scope| {
+            let handles: Vec<_> = (0..blocks)
+                .step_by(per_thread)
+                .map(|start| {
+                    let end = (start + per_thread).min(blocks);
+                    scope.spawn(move || range_peaks(buffer, channels, start..end, bounds, channel))
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect()
+        })
+    }
+
+    // Rounds to the nearest whole frame, so float error in `rate * seconds`
+    // can't drop a frame and positions never land between the channels of an
+    // interleaved frame.
+    fn seconds_to_frames(&self, seconds: f64) -> i64 {
+        (self.sample_rate.0 as f64 * seconds).round() as i64
+    }
+
+    // Positions index the interleaved buffer, so one frame is `channels` samples.
+    fn seconds_to_samples(&self, seconds: f64) -> i64 {
+        self.seconds_to_frames(seconds) * self.channels as i64
     }
 
     pub fn buffer_data_dasp(&self) -> Vec<i32> {
         let rb = *self.rb.lock().unwrap();
 
-        let (data, _) = rb.slices();
+        // Once the ring has wrapped, `slices` splits at the write head with the
+        // oldest samples in the first slice.
+        let (oldest, newest) = rb.slices();
+
+        [oldest, newest].concat()
+    }
+
+    // The ring buffer together with the playback position it ends at. Locks
+    // in the same order as the stream callback so the two always agree.
+    pub fn snapshot(&self) -> (Vec<i32>, usize) {
+        let position = self.position.lock().unwrap();
+
+        (self.buffer_data_dasp(), *position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::RecordingSink;
+    use std::path::PathBuf;
+
+    fn write_wav(name: &str, sample_rate: u32, channels: u16, samples: &[i16]) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("oscli-output-{}-{}.wav", std::process::id(), name));
+        let spec = hound::WavSpec {
+            channels,
+            sample_rate,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for sample in samples {
+            writer.write_sample(*sample).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        path
+    }
+
+    // An `Output` playing `samples` into a recording, with no pause fade so
+    // output starts at full level.
+    fn recorded(
+        name: &str,
+        sample_rate: u32,
+        channels: u16,
+        samples: &[i16],
+    ) -> (Output, RecordingSink) {
+        let path = write_wav(name, sample_rate, channels, samples);
+        let sink = RecordingSink::new();
+        let mut output = Output::new();
+        output.set_output(Box::new(sink.clone()));
+        output.set_pause_fade(Duration::ZERO);
+        output.load_file(File::open(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        (output, sink)
+    }
+
+    fn to_f32(samples: &[i16]) -> Vec<f32> {
+        samples.iter().map(cpal::Sample::to_f32).collect()
+    }
+
+    // Refuses one open once `fail` is set, like a device that can't take the
+    // new track's format but still takes the old one.
+    struct PickySink {
+        inner: RecordingSink,
+        fail: Arc<AtomicBool>,
+    }
+
+    impl OutputSink for PickySink {
+        fn open(
+            &mut self,
+            sample_rate: cpal::SampleRate,
+            channels: cpal::ChannelCount,
+            buffer_frames: Option<u32>,
+            playback: Playback,
+        ) -> anyhow::Result<cpal::StreamConfig> {
+            if self.fail.swap(false, Ordering::Relaxed) {
+                self.inner.close();
+                anyhow::bail!("Unsupported format");
+            }
+            self.inner
+                .open(sample_rate, channels, buffer_frames, playback)
+        }
+
+        fn close(&mut self) {
+            self.inner.close()
+        }
+
+        fn is_open(&self) -> bool {
+            self.inner.is_open()
+        }
+
+        fn play(&mut self) {
+            self.inner.play()
+        }
+
+        fn pause(&mut self) {
+            self.inner.pause()
+        }
+    }
+
+    #[test]
+    fn failed_open_keeps_the_current_track() {
+        let first = write_wav("picky-first", 8000, 1, &[1000, 2000, 3000, 4000]);
+        let second = write_wav("picky-second", 44100, 2, &[-1000; 8]);
+
+        let sink = RecordingSink::new();
+        let fail = Arc::new(AtomicBool::new(false));
+        let mut output = Output::new();
+        output.set_output(Box::new(PickySink {
+            inner: sink.clone(),
+            fail: fail.clone(),
+        }));
+        output.set_pause_fade(Duration::ZERO);
+        let events = output.events().unwrap();
+
+        output.load_file(File::open(&first).unwrap()).unwrap();
+        output.play();
+        sink.pull(2);
+
+        fail.store(true, Ordering::Relaxed);
+        assert!(output.load_file(File::open(&second).unwrap()).is_err());
+        std::fs::remove_file(&first).unwrap();
+        std::fs::remove_file(&second).unwrap();
+
+        assert_eq!(*output.buffer, vec![1000, 2000, 3000, 4000]);
+        assert_eq!((output.sample_rate.0, output.channels), (8000, 1));
+        assert!(output.is_loaded());
+        let loads = events
+            .try_iter()
+            .filter(|event| matches!(event, OutputEvent::Loaded { .. }))
+            .count();
+        assert_eq!(loads, 1);
+
+        // Reopened where it was and still playing.
+        sink.pull(2);
+        assert_eq!(sink.samples(), to_f32(&[1000, 2000, 3000, 4000]));
+    }
+
+    #[test]
+    fn play_records_the_decoded_samples() {
+        let samples = [0, 1000, -1000, 16384, -16384, i16::MAX, i16::MIN, 5];
+        let (mut output, sink) = recorded("play", 8000, 2, &samples);
+
+        // The stream runs from the load but stays silent until played.
+        sink.pull(2);
+        assert_eq!(sink.samples(), vec![0.0; 4]);
+        sink.clear();
+
+        output.play();
+        sink.pull(4);
+
+        assert_eq!(sink.samples(), to_f32(&samples));
+    }
+
+    #[test]
+    fn seeking_moves_the_output() {
+        let samples: Vec<i16> = (0..16).map(|sample| sample * 100).collect();
+        let (mut output, sink) = recorded("seek", 4, 1, &samples);
+
+        output.play();
+        output.set_position(2.0);
+        sink.pull(2);
+        output.backward(1.0);
+        sink.pull(2);
+
+        assert_eq!(sink.samples(), to_f32(&[800, 900, 600, 700]));
+    }
+
+    #[test]
+    fn volume_scales_the_recorded_samples() {
+        let samples = [1000, -2000, 4000, -8000];
+        let (mut output, sink) = recorded("volume", 8000, 1, &samples);
+
+        output.set_volume(0.5);
+        output.play();
+        sink.pull(4);
+
+        let expected: Vec<f32> = to_f32(&samples).iter().map(|sample| sample * 0.5).collect();
+        assert_eq!(sink.samples(), expected);
+    }
+
+    #[test]
+    fn pause_ramps_to_silence() {
+        let samples = vec![16000; 2000];
+        let (mut output, sink) = recorded("pause", 1000, 1, &samples);
+
+        output.play();
+        sink.pull(100);
+        output.set_pause_fade(Duration::from_millis(10));
+        output.pause();
+        // Mid-fade the device keeps running.
+        sink.pull(5);
+        output.poll();
+        sink.pull(95);
+
+        let recorded = sink.samples();
+        let full = cpal::Sample::to_f32(&16000i16);
+        let ramp = &recorded[100..110];
+
+        assert!(recorded[..100].iter().all(|sample| *sample == full));
+        assert!(ramp.windows(2).all(|pair| pair[1] < pair[0]));
+        assert!(ramp[0] < full && ramp[9] < 0.01);
+        assert!(recorded[110..].iter().all(|sample| *sample == 0.0));
+        assert!(!output.is_playing());
+
+        // Faded out, so the device stops until a handle resumes it.
+        output.poll();
+        sink.pull(10);
+        assert_eq!(sink.samples().len(), 200);
+
+        output.handle().play();
+        output.poll();
+        sink.pull(10);
+        let resumed = &sink.samples()[200..];
+        assert_eq!(resumed.len(), 10);
+        assert!(resumed.windows(2).all(|pair| pair[1] > pair[0]));
+    }
+
+    #[test]
+    fn channel_peaks_use_fixed_blocks() {
+        let mut samples = vec![];
+        for frame in 0..1500 {
+            let left = if frame < PEAK_BLOCK_FRAMES { 100 } else { -200 };
+            samples.extend([left, 300]);
+        }
+        let (output, _) = recorded("blocks", 44100, 2, &samples);
+
+        assert_eq!(
+            output.channel_peaks(0).unwrap(),
+            vec![
+                (100.0 / 32768.0, 100.0 / 32768.0),
+                (-200.0 / 32768.0, -200.0 / 32768.0)
+            ]
+        );
+        assert_eq!(
+            output.channel_rms(0).unwrap(),
+            vec![100.0 / 32768.0, 200.0 / 32768.0]
+        );
+        assert_eq!(output.peaks().frames_per_peak, PEAK_BLOCK_FRAMES as u32);
+        assert!(output.channel_peaks(2).is_err());
+    }
+
+    #[test]
+    fn undrained_events_keep_the_latest() {
+        let (mut output, _) = recorded("events", 8000, 1, &[0; 8]);
+        let events = output.events().unwrap();
+
+        for _ in 0..EVENT_CAPACITY {
+            output.play();
+            output.pause();
+        }
+        output.set_position(1.0);
+
+        let received: Vec<_> = events.try_iter().collect();
+        assert_eq!(received.len(), EVENT_CAPACITY);
+        assert_eq!(received.last(), Some(&OutputEvent::Finished));
+    }
+
+    #[test]
+    fn exported_loops_join_without_a_seam() {
+        // 100 Hz at 1 kHz: ten samples per cycle, so 0.5 s is exactly five.
+        let samples: Vec<i16> = (0..2000)
+            .map(|i| ((i as f32 * std::f32::consts::TAU / 10.0).sin() * 10000.0) as i16)
+            .collect();
+        let (output, _) = recorded("loop-source", 1000, 1, &samples);
+
+        let path =
+            std::env::temp_dir().join(format!("oscli-output-{}-loop.wav", std::process::id()));
+        output.export_loop_wav(0.3, 0.8, &path).unwrap();
+        let exported: Vec<i16> = hound::WavReader::open(&path)
+            .unwrap()
+            .samples::<i16>()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(exported, samples[300..800]);
+
+        // Across the seam the step is no bigger than anywhere inside the loop.
+        let twice = [exported.clone(), exported].concat();
+        let steps: Vec<i32> = twice
+            .windows(2)
+            .map(|pair| (pair[1] as i32 - pair[0] as i32).abs())
+            .collect();
+        let largest = *steps[..499].iter().max().unwrap();
+        assert!(steps[499] <= largest);
+    }
 
-        data.to_vec()
+    #[test]
+    fn seeks_land_on_exact_frames() {
+        for channels in [1, 2] {
+            let samples = vec![0; 2 * 44100 * channels as usize];
+            let (mut output, _) = recorded("exact-seek", 44100, channels, &samples);
+            let channels = channels as usize;
+
+            // 0.7 * 44100 is 30869.999... in floating point.
+            output.set_position(0.7);
+            assert_eq!(*output.position.lock().unwrap(), 30870 * channels);
+
+            output.forward(1.0);
+            assert_eq!(*output.position.lock().unwrap(), (30870 + 44100) * channels);
+
+            output.backward(0.5);
+            assert_eq!(*output.position.lock().unwrap(), (30870 + 22050) * channels);
+            assert_eq!(output.position_secs(), 1.2);
+        }
+    }
+
+    #[test]
+    fn ring_buffer_reads_oldest_first_after_wrapping() {
+        let samples: Vec<i16> = (0..3000).collect();
+        let (mut output, sink) = recorded("ring", 8000, 1, &samples);
+
+        output.play();
+        sink.pull(3000);
+
+        let expected: Vec<i32> = (3000 - RING_BUFFER_SIZE as i32..3000).collect();
+        assert_eq!(output.buffer_data_dasp(), expected);
+    }
+
+    #[test]
+    fn seeking_past_the_end_finishes_once() {
+        let (mut output, sink) = recorded("past-end", 10, 1, &[100; 20]);
+        let events = output.events().unwrap();
+
+        output.play();
+        output.set_position(5.0);
+        output.forward(1.0);
+        sink.pull(4);
+
+        assert!(output.is_finished());
+        assert_eq!(*output.position.lock().unwrap(), 20);
+        assert!(sink.samples().iter().all(|sample| *sample == 0.0));
+        let finished = events
+            .try_iter()
+            .filter(|event| *event == OutputEvent::Finished)
+            .count();
+        assert_eq!(finished, 1);
+    }
+
+    #[test]
+    fn seeking_past_the_end_wraps_when_looping() {
+        let (mut output, _) = recorded("past-end-loop", 10, 1, &[100; 20]);
+        let events = output.events().unwrap();
+
+        output.set_loop(true);
+        output.set_position(1.5);
+        output.forward(1.0);
+
+        assert_eq!(*output.position.lock().unwrap(), 0);
+        assert!(!output.is_finished());
+        assert!(!events
+            .try_iter()
+            .any(|event| event == OutputEvent::Finished));
+    }
+
+    #[test]
+    fn reverse_plays_the_frames_backwards() {
+        let samples = [1, -1, 2, -2, 3, -3, 4, -4, 5, -5];
+        let (mut output, sink) = recorded("reverse", 8000, 2, &samples);
+
+        output.set_reverse(true);
+        output.set_position(output.duration_secs());
+        output.play();
+        sink.pull(5);
+
+        let reversed: Vec<i16> = samples.chunks(2).rev().flatten().cloned().collect();
+        assert_eq!(sink.samples(), to_f32(&reversed));
+        assert!(output.is_finished());
+
+        sink.pull(2);
+        assert!(sink.samples()[10..].iter().all(|sample| *sample == 0.0));
+    }
+
+    #[test]
+    fn channel_trims_apply_independently() {
+        let samples = [1000, 1000, -2000, -2000];
+        let (mut output, sink) = recorded("trims", 8000, 2, &samples);
+
+        output.set_channel_gain(0, 0.5).unwrap();
+        output.set_channel_gain(1, 2.0).unwrap();
+        output.play();
+        sink.pull(2);
+
+        let full = to_f32(&samples);
+        assert_eq!(
+            sink.samples(),
+            vec![full[0] * 0.5, full[1] * 2.0, full[2] * 0.5, full[3] * 2.0]
+        );
+
+        output.set_channel_gain(1, 10.0).unwrap();
+        assert_eq!(output.channel_gain(1), Some(MAX_CHANNEL_GAIN));
+        assert_eq!(output.channel_gain(0), Some(0.5));
+    }
+
+    #[test]
+    fn channel_trim_rejects_bad_input() {
+        let (mut output, _) = recorded("trim-errors", 8000, 2, &[0; 4]);
+
+        assert!(output.set_channel_gain(2, 1.0).is_err());
+        assert!(output.set_channel_gain(0, f32::NAN).is_err());
+        assert_eq!(output.channel_gain(0), Some(1.0));
+        assert_eq!(output.channel_gain(2), None);
+    }
+
+    #[test]
+    fn display_auto_fit_leaves_output_alone() {
+        use crate::vertex::{auto_fit_scale, generate_vertexes};
+
+        let samples: Vec<i16> = (0..64).map(|i| i * 100 - 3200).collect();
+        let (mut plain, plain_sink) = recorded("fit-plain", 8000, 1, &samples);
+        let (mut fitted, fitted_sink) = recorded("fit-scaled", 8000, 1, &samples);
+
+        plain.play();
+        fitted.play();
+        for _ in 0..4 {
+            plain_sink.pull(16);
+            // What the renderer does with each frame while auto-fit is on.
+            let (data, _) = fitted.snapshot();
+            let scale = auto_fit_scale(&data);
+            assert!(scale > 1.0);
+            generate_vertexes(&data, 1, scale, None);
+            fitted_sink.pull(16);
+        }
+        assert_eq!(fitted_sink.samples(), plain_sink.samples());
+
+        // Normalize is the switch that changes what is heard.
+        plain.set_normalize(true);
+        plain.set_position(0.0);
+        plain_sink.clear();
+        plain_sink.pull(64);
+        assert_ne!(plain_sink.samples(), fitted_sink.samples());
+    }
+
+    #[test]
+    fn parallel_peaks_match_a_serial_pass() {
+        let samples: Vec<i16> = (0..20_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 16) as i16)
+            .collect();
+        let (output, _) = recorded("parallel", 44100, 2, &samples);
+        let frames = samples.len() / 2;
+
+        for columns in [1, 7, 333, 1000] {
+            let bounds = equal_slices(frames, columns);
+            for channel in [None, Some(0), Some(1)] {
+                assert_eq!(
+                    output.block_peaks(columns, bounds, channel),
+                    range_peaks(&samples, 2, 0..columns, bounds, channel)
+                );
+            }
+        }
+
+        let blocks = frames.div_ceil(PEAK_BLOCK_FRAMES);
+        assert_eq!(
+            output.block_peaks(blocks, fixed_blocks(frames), Some(1)),
+            range_peaks(&samples, 2, 0..blocks, fixed_blocks(frames), Some(1))
+        );
+    }
+
+    #[test]
+    fn handle_controls_volume_mute_and_speed_from_another_thread() {
+        let (mut output, sink) = recorded("handle", 8000, 1, &[1000; 8]);
+        let handle = output.handle();
+
+        thread::spawn(move || {
+            handle.set_volume(0.25);
+            handle.toggle_mute();
+            handle.set_speed(10.0);
+        })
+        .join()
+        .unwrap();
+
+        assert_eq!(output.volume(), 0.25);
+        assert!(output.is_muted());
+        assert_eq!(output.speed(), MAX_SPEED);
+
+        output.handle().set_muted(false);
+        output.handle().set_speed(1.0);
+        output.play();
+        sink.pull(2);
+        assert_eq!(
+            sink.samples(),
+            vec![cpal::Sample::to_f32(&1000i16) * 0.25; 2]
+        );
+    }
+
+    #[test]
+    fn handle_plays_and_pauses_a_fresh_load() {
+        let mut unloaded = Output::new();
+        let unloaded_events = unloaded.events().unwrap();
+        unloaded.handle().play();
+        assert_eq!(unloaded_events.try_recv(), None);
+
+        let (mut output, sink) = recorded("handle-play", 8000, 1, &[1000; 8]);
+        let events = output.events().unwrap();
+        let handle = output.handle();
+
+        thread::spawn(move || handle.play()).join().unwrap();
+        sink.pull(2);
+        assert_eq!(sink.samples(), to_f32(&[1000, 1000]));
+
+        output.handle().pause();
+        sink.clear();
+        sink.pull(2);
+        assert_eq!(sink.samples(), vec![0.0; 2]);
+
+        let states: Vec<_> = events
+            .try_iter()
+            .filter(|event| matches!(event, OutputEvent::StateChanged(_)))
+            .collect();
+        assert_eq!(
+            states,
+            vec![
+                OutputEvent::StateChanged(PlaybackState::Playing),
+                OutputEvent::StateChanged(PlaybackState::Paused),
+            ]
+        );
     }
 }
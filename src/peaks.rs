@@ -0,0 +1,291 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+// Binary peak file, all integers little-endian (see the README for details):
+//
+//   0   4  magic "OSCP"
+//   4   1  version (1)
+//   5   1  sample format: 0 = f32, 1 = i16 (value * 32767, rounded)
+//   6   2  reserved, zero
+//   8   4  sample rate (u32)
+//  12   2  channels (u16)
+//  14   2  reserved, zero
+//  16   4  frames per peak (u32)
+//  20   4  peak count per channel (u32)
+//  24      peaks: for each block, for each channel, min then max
+const MAGIC: &[u8; 4] = b"OSCP";
+const VERSION: u8 = 1;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PeakFormat {
+    F32,
+    I16,
+}
+
+// Min/max envelope of every channel, block-major: block `b` of channel `c` is
+// `peaks[b * channels + c]`, in -1..1.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Peaks {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub frames_per_peak: u32,
+    pub peaks: Vec<(f32, f32)>,
+}
+
+impl Peaks {
+    pub fn save<P: AsRef<Path>>(&self, path: P, format: PeakFormat) -> anyhow::Result<()> {
+        let channels = self.channels.max(1) as usize;
+        if !self.peaks.len().is_multiple_of(channels) {
+            anyhow::bail!(
+                "{} peaks don't divide into {} channels",
+                self.peaks.len(),
+                channels
+            );
+        }
+
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION, format as u8, 0, 0])?;
+        writer.write_all(&self.sample_rate.to_le_bytes())?;
+        writer.write_all(&self.channels.to_le_bytes())?;
+        writer.write_all(&[0, 0])?;
+        writer.write_all(&self.frames_per_peak.to_le_bytes())?;
+        writer.write_all(&((self.peaks.len() / channels) as u32).to_le_bytes())?;
+
+        for &(min, max) in &self.peaks {
+            for value in [min, max] {
+                match format {
+                    PeakFormat::F32 => writer.write_all(&value.to_le_bytes())?,
+                    PeakFormat::I16 => writer.write_all(&quantize(value).to_le_bytes())?,
+                }
+            }
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+
+        let mut header = [0; 24];
+        reader.read_exact(&mut header)?;
+
+        if &header[0..4] != MAGIC {
+            anyhow::bail!("Not a peak file");
+        }
+        if header[4] != VERSION {
+            anyhow::bail!("Unsupported peak file version {}", header[4]);
+        }
+        let format = match header[5] {
+            0 => PeakFormat::F32,
+            1 => PeakFormat::I16,
+            other => anyhow::bail!("Unknown peak sample format {}", other),
+        };
+
+        let u32_at = |at: usize| u32::from_le_bytes(header[at..at + 4].try_into().unwrap());
+        let sample_rate = u32_at(8);
+        let channels = u16::from_le_bytes([header[12], header[13]]);
+        let frames_per_peak = u32_at(16);
+        let count = u32_at(20) as u64 * channels as u64;
+
+        // The count is untrusted, so check the file really holds that many
+        // peaks before allocating for them.
+        let peak_size = match format {
+            PeakFormat::F32 => 8,
+            PeakFormat::I16 => 4,
+        };
+        if count * peak_size > len.saturating_sub(24) {
+            anyhow::bail!(
+                "Peak file is truncated: header claims {} peaks, {} bytes follow",
+                count,
+                len.saturating_sub(24)
+            );
+        }
+        let count = count as usize;
+
+        let mut read_value = || -> std::io::Result<f32> {
+            Ok(match format {
+                PeakFormat::F32 => {
+                    let mut bytes = [0; 4];
+                    reader.read_exact(&mut bytes)?;
+                    f32::from_le_bytes(bytes)
+                }
+                PeakFormat::I16 => {
+                    let mut bytes = [0; 2];
+                    reader.read_exact(&mut bytes)?;
+                    i16::from_le_bytes(bytes) as f32 / i16::MAX as f32
+                }
+            })
+        };
+
+        let mut peaks = Vec::with_capacity(count);
+        for _ in 0..count {
+            peaks.push((read_value()?, read_value()?));
+        }
+
+        Ok(Self {
+            sample_rate,
+            channels,
+            frames_per_peak,
+            peaks,
+        })
+    }
+}
+
+// Peaks at the base resolution and every power-of-two coarser one down to a
+// single block, so a view can pick a ready-made level instead of reducing
+// samples every frame. All levels together take at most twice the base.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PeakPyramid {
+    levels: Vec<Peaks>,
+}
+
+impl PeakPyramid {
+    pub fn new(base: Peaks) -> Self {
+        let mut levels = vec![base];
+        loop {
+            let last = levels.last().unwrap();
+            if last.peaks.len() <= last.channels.max(1) as usize {
+                break;
+            }
+            let next = last.halve();
+            levels.push(next);
+        }
+
+        Self { levels }
+    }
+
+    pub fn levels(&self) -> &[Peaks] {
+        &self.levels
+    }
+
+    // The coarsest level that still has at least one peak per pixel.
+    pub fn level_for(&self, frames_per_pixel: f64) -> &Peaks {
+        self.levels
+            .iter()
+            .rev()
+            .find(|level| level.frames_per_peak as f64 <= frames_per_pixel)
+            .unwrap_or(&self.levels[0])
+    }
+}
+
+impl Peaks {
+    // Merges neighbouring blocks pairwise; an odd last block is kept as is.
+    fn halve(&self) -> Self {
+        let channels = self.channels.max(1) as usize;
+        let blocks = self.peaks.len() / channels;
+
+        let peaks = (0..blocks.div_ceil(2))
+            .flat_map(|block| {
+                (0..channels).map(move |channel| {
+                    let first = self.peaks[2 * block * channels + channel];
+                    match self.peaks.get((2 * block + 1) * channels + channel) {
+                        Some(second) => (first.0.min(second.0), first.1.max(second.1)),
+                        None => first,
+                    }
+                })
+            })
+            .collect();
+
+        Self {
+            sample_rate: self.sample_rate,
+            channels: self.channels,
+            frames_per_peak: self.frames_per_peak.saturating_mul(2),
+            peaks,
+        }
+    }
+}
+
+fn quantize(value: f32) -> i16 {
+    (value.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("oscli-peaks-{}-{}", std::process::id(), name))
+    }
+
+    fn sample_peaks() -> Peaks {
+        Peaks {
+            sample_rate: 44100,
+            channels: 2,
+            frames_per_peak: 1024,
+            peaks: vec![(-0.5, 0.25), (-1.0, 1.0), (0.0, 0.0), (-0.125, 0.75)],
+        }
+    }
+
+    #[test]
+    fn round_trips_f32() {
+        let path = temp_path("f32.bin");
+        let peaks = sample_peaks();
+
+        peaks.save(&path, PeakFormat::F32).unwrap();
+        let loaded = Peaks::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded, peaks);
+    }
+
+    #[test]
+    fn round_trips_i16_within_quantization() {
+        let path = temp_path("i16.bin");
+        let peaks = sample_peaks();
+
+        peaks.save(&path, PeakFormat::I16).unwrap();
+        let size = std::fs::metadata(&path).unwrap().len();
+        let loaded = Peaks::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(size, 24 + peaks.peaks.len() as u64 * 4);
+        assert_eq!(
+            (loaded.sample_rate, loaded.channels, loaded.frames_per_peak),
+            (44100, 2, 1024)
+        );
+        assert_eq!(loaded.peaks.len(), peaks.peaks.len());
+        for (loaded, original) in loaded.peaks.iter().zip(&peaks.peaks) {
+            assert!((loaded.0 - original.0).abs() <= 1.0 / i16::MAX as f32);
+            assert!((loaded.1 - original.1).abs() <= 1.0 / i16::MAX as f32);
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_file() {
+        let path = temp_path("truncated.bin");
+        sample_peaks().save(&path, PeakFormat::F32).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+
+        let result = Peaks::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn rejects_forged_count_without_allocating() {
+        let path = temp_path("forged.bin");
+        let mut header = Vec::new();
+        header.extend_from_slice(MAGIC);
+        header.extend_from_slice(&[VERSION, PeakFormat::F32 as u8, 0, 0]);
+        header.extend_from_slice(&44100u32.to_le_bytes());
+        header.extend_from_slice(&u16::MAX.to_le_bytes());
+        header.extend_from_slice(&[0, 0]);
+        header.extend_from_slice(&1024u32.to_le_bytes());
+        header.extend_from_slice(&u32::MAX.to_le_bytes());
+        std::fs::write(&path, &header).unwrap();
+
+        let result = Peaks::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(result.unwrap_err().to_string().contains("truncated"));
+    }
+}
@@ -1,30 +1,173 @@
-use std::{fs::File, iter};
+use std::{collections::VecDeque, fs::File, iter, ops::Range, path::PathBuf};
 
-use wgpu::util::DeviceExt;
 use winit::{
+    dpi::LogicalSize,
     event::*,
     event_loop::{ControlFlow, EventLoop},
-    window::Window,
+    window::{Window, WindowBuilder},
 };
 
-use crate::output::Output;
-use crate::vertex::{generate_vertexes, Vertex};
+use crate::cue::{load_cue, CueTrack};
+use crate::decode::Loading;
+use crate::meter::{channel_peaks, mid_side_peaks, PeakHold};
+use crate::output::{Output, MAX_VOLUME, RING_BUFFER_SIZE};
+use crate::text::{generate_text, ADVANCE, LINE_HEIGHT};
+use crate::vertex::{
+    auto_fit_scale, color_lanes, dim, find_trigger, generate_cursor_line, generate_level_bars,
+    generate_peak_ticks, generate_progress_bar, generate_trace, generate_vertexes,
+    generate_volume_bar, mirror, offset_lanes, rms_bands, sweep, tint_by_energy, tint_by_spectrum,
+    MonoMode, TriggerMode, Vertex,
+};
+use crate::view::ViewTransform;
+
+const PEAK_DECAY_DB_PER_SEC: f32 = 12.0;
+const MIN_AMPLITUDE_CEILING: f32 = 1e-3;
+const MAX_OVERLAY_VERTICES: usize = 16384;
+const HUD_SCALE: u32 = 2;
+pub const MIN_OSCILLOSCOPE_RANGE: f32 = 0.001;
+const MAX_TRACE_VERTICES: usize = 8192;
+// A mirrored mono lane draws every ring buffer sample twice, and the RMS band
+// once more.
+const MAX_SCOPE_VERTICES: usize = 3 * RING_BUFFER_SIZE;
+const BACKGROUND_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+const VOLUME_STEP: f32 = 0.05;
+const SPEED_STEP: f32 = 0.25;
+// Seconds the volume bar stays up after a change; it fades over the last half.
+const VOLUME_DISPLAY_SECS: f32 = 1.0;
+
+// What the HUD time readout shows.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum TimeDisplay {
+    #[default]
+    Elapsed,
+    Remaining,
+    Total,
+}
+
+impl TimeDisplay {
+    fn next(self) -> Self {
+        match self {
+            TimeDisplay::Elapsed => TimeDisplay::Remaining,
+            TimeDisplay::Remaining => TimeDisplay::Total,
+            TimeDisplay::Total => TimeDisplay::Elapsed,
+        }
+    }
+}
+
+// Options fixed when the surface and pipelines are created, plus starting
+// values for the ones that have setters on `State`.
+#[derive(Clone, Debug)]
+pub struct RendererConfig {
+    pub title: String,
+    // Logical pixels; `None` leaves it to the platform.
+    pub window_size: Option<(u32, u32)>,
+    pub present_mode: wgpu::PresentMode,
+    pub visible_samples: usize,
+    pub oscilloscope_range: f32,
+    pub auto_fit: bool,
+    pub amplitude_ceiling: f32,
+    pub follow_playhead: bool,
+    pub single_lane: bool,
+    pub symmetric: bool,
+    pub mono_display: MonoMode,
+    pub sweep: bool,
+    pub energy_coloring: bool,
+    pub spectral_coloring: bool,
+    pub rms_band: bool,
+    pub trigger: TriggerMode,
+    pub trigger_level: f32,
+    pub peak_decay: f32,
+    pub mid_side: bool,
+    pub time_display: TimeDisplay,
+    pub hud: bool,
+}
 
-struct State {
+impl Default for RendererConfig {
+    fn default() -> Self {
+        Self {
+            title: String::from("oscli"),
+            window_size: None,
+            present_mode: wgpu::PresentMode::Fifo,
+            visible_samples: 2048,
+            oscilloscope_range: f32::INFINITY,
+            auto_fit: false,
+            amplitude_ceiling: 1.0,
+            follow_playhead: true,
+            single_lane: false,
+            symmetric: false,
+            mono_display: MonoMode::default(),
+            sweep: false,
+            energy_coloring: false,
+            spectral_coloring: false,
+            rms_band: false,
+            trigger: TriggerMode::Off,
+            trigger_level: 0.0,
+            peak_decay: PEAK_DECAY_DB_PER_SEC,
+            mid_side: false,
+            time_display: TimeDisplay::Elapsed,
+            hud: false,
+        }
+    }
+}
+
+pub struct State {
     surface: wgpu::Surface,
     device: wgpu::Device,
     queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
+    adapter_info: wgpu::AdapterInfo,
     size: winit::dpi::PhysicalSize<u32>,
     output: Output,
     vertex_buffer: wgpu::Buffer,
-    render_pipeline: wgpu::RenderPipeline,
-    num_vertices: u32,
+    lanes: Vec<Range<u32>>,
+    overlay_pipeline: wgpu::RenderPipeline,
+    overlay_buffer: wgpu::Buffer,
+    num_overlay_vertices: u32,
+    trace_pipeline: wgpu::RenderPipeline,
+    trace_buffer: wgpu::Buffer,
+    num_trace_vertices: u32,
+    visible_samples: usize,
     playing: bool,
+    pending: VecDeque<PathBuf>,
+    loading: Option<Loading>,
+    superseded: Vec<Loading>,
+    autoplay: bool,
+    auto_fit: bool,
+    amplitude_ceiling: f32,
+    follow_playhead: bool,
+    oscilloscope_range: f32,
+    sweep: bool,
+    energy_coloring: bool,
+    spectral_coloring: bool,
+    rms_band: bool,
+    trigger: TriggerMode,
+    trigger_level: f32,
+    single_lane: bool,
+    symmetric: bool,
+    baselines: Vec<f32>,
+    lane_colors: Vec<[f32; 3]>,
+    view: ViewTransform,
+    view_observer: Option<Box<dyn FnMut(ViewTransform, usize)>>,
+    cursor: Option<[f32; 2]>,
+    current: Option<PathBuf>,
+    cue: Vec<CueTrack>,
+    playlist: bool,
+    selected: usize,
+    drag: Option<usize>,
+    title: String,
+    time_display: TimeDisplay,
+    volume_shown: f32,
+    hud: bool,
+    fps: f32,
+    mono_display: MonoMode,
+    meters: Vec<PeakHold>,
+    mid_side: bool,
+    mid_side_meters: Vec<PeakHold>,
+    peak_decay: f32,
 }
 
 impl State {
-    async fn new(window: &Window) -> Self {
+    pub async fn new(window: &Window, renderer_config: RendererConfig) -> Self {
         let size = window.inner_size();
 
         let instance = wgpu::Instance::new(wgpu::Backends::all());
@@ -50,25 +193,48 @@ impl State {
             .await
             .unwrap();
 
+        let adapter_info = adapter.get_info();
+
         let output = Output::new();
 
         let data = output.buffer_data_dasp();
 
-        let vertecies = generate_vertexes(&data[0..data.len()], output.channels as i16);
+        let vertecies = generate_vertexes(&data[0..data.len()], output.channels as i16, 1.0, None);
 
-        let mut num_verticies = 0;
+        let mut lanes = vec![];
 
         let mut buffer_content = vec![];
 
         for mut chan in vertecies {
-            num_verticies = num_verticies + chan.len();
+            let start = buffer_content.len() as u32;
             buffer_content.append(&mut chan);
+            lanes.push(start..buffer_content.len() as u32);
         }
 
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Vertex Buffer"),
-            contents: bytemuck::cast_slice(&buffer_content[0..buffer_content.len()]),
+            size: (MAX_SCOPE_VERTICES * std::mem::size_of::<Vertex>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        queue.write_buffer(
+            &vertex_buffer,
+            0,
+            bytemuck::cast_slice(&buffer_content[0..buffer_content.len()]),
+        );
+
+        let overlay_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Overlay Buffer"),
+            size: (MAX_OVERLAY_VERTICES * std::mem::size_of::<Vertex>()) as wgpu::BufferAddress,
             usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let trace_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Trace Buffer"),
+            size: (MAX_TRACE_VERTICES * std::mem::size_of::<Vertex>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
         });
 
         let config = wgpu::SurfaceConfiguration {
@@ -77,7 +243,7 @@ impl State {
             format: surface.get_supported_formats(&adapter)[0],
             width: size.width,
             height: size.height,
-            present_mode: wgpu::PresentMode::Fifo,
+            present_mode: renderer_config.present_mode,
         };
 
         surface.configure(&device, &config);
@@ -94,53 +260,84 @@ impl State {
                 push_constant_ranges: &[],
             });
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &[Vertex::desc()],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::PointList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw, // 2.
-                cull_mode: Some(wgpu::Face::Back),
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: None, // 1.
-            multisample: wgpu::MultisampleState {
-                count: 1,                         // 2.
-                mask: !0,                         // 3.
-                alpha_to_coverage_enabled: false, // 4.
-            },
-            multiview: None, // 5.
-        });
+        let overlay_pipeline = create_pipeline(
+            &device,
+            &render_pipeline_layout,
+            &shader,
+            config.format,
+            wgpu::PrimitiveTopology::LineList,
+        );
 
-        Self {
+        let trace_pipeline = create_pipeline(
+            &device,
+            &render_pipeline_layout,
+            &shader,
+            config.format,
+            wgpu::PrimitiveTopology::LineStrip,
+        );
+
+        let mut state = Self {
             surface,
             device,
             queue,
             config,
+            adapter_info,
             size,
             output: Output::new(),
             vertex_buffer,
-            render_pipeline,
-            num_vertices: num_verticies as u32,
+            lanes,
+            overlay_pipeline,
+            overlay_buffer,
+            num_overlay_vertices: 0,
+            trace_pipeline,
+            trace_buffer,
+            num_trace_vertices: 0,
+            visible_samples: 2048,
             playing: false,
-        }
+            pending: VecDeque::new(),
+            loading: None,
+            superseded: vec![],
+            autoplay: false,
+            auto_fit: renderer_config.auto_fit,
+            amplitude_ceiling: 1.0,
+            follow_playhead: renderer_config.follow_playhead,
+            oscilloscope_range: f32::INFINITY,
+            sweep: renderer_config.sweep,
+            energy_coloring: renderer_config.energy_coloring,
+            spectral_coloring: renderer_config.spectral_coloring,
+            rms_band: renderer_config.rms_band,
+            trigger: TriggerMode::Off,
+            trigger_level: 0.0,
+            single_lane: renderer_config.single_lane,
+            symmetric: renderer_config.symmetric,
+            baselines: vec![],
+            lane_colors: vec![],
+            view: ViewTransform::default(),
+            view_observer: None,
+            cursor: None,
+            current: None,
+            cue: vec![],
+            playlist: false,
+            selected: 0,
+            drag: None,
+            title: renderer_config.title.clone(),
+            time_display: renderer_config.time_display,
+            volume_shown: 0.0,
+            hud: renderer_config.hud,
+            fps: 0.0,
+            mono_display: renderer_config.mono_display,
+            meters: vec![],
+            mid_side: renderer_config.mid_side,
+            mid_side_meters: vec![],
+            peak_decay: renderer_config.peak_decay,
+        };
+
+        state.set_visible_samples(renderer_config.visible_samples);
+        state.set_oscilloscope_range(renderer_config.oscilloscope_range);
+        state.set_amplitude_ceiling(renderer_config.amplitude_ceiling);
+        state.set_trigger(renderer_config.trigger, renderer_config.trigger_level);
+
+        state
     }
 
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
@@ -152,33 +349,800 @@ impl State {
         }
     }
 
-    #[allow(unused_variables)]
+    pub fn adapter_info(&self) -> wgpu::AdapterInfo {
+        self.adapter_info.clone()
+    }
+
+    fn print_diagnostics(&self) {
+        let adapter_info = self.adapter_info();
+        println!(
+            "adapter: {} ({:?}, {:?})",
+            adapter_info.name, adapter_info.backend, adapter_info.device_type
+        );
+        println!("surface format: {:?}", self.config.format);
+        println!("present mode: {:?}", self.config.present_mode);
+        println!("audio device: {}", self.output.device_name());
+        match self.output.device_config() {
+            Some(config) => println!(
+                "audio config: {} Hz, {} channels, {:?}",
+                config.sample_rate().0,
+                config.channels(),
+                config.sample_format()
+            ),
+            None => println!("audio config: unavailable"),
+        }
+        if let Some(config) = self.output.stream_config() {
+            println!(
+                "stream config: {} Hz, {} channels, {:?}",
+                config.sample_rate.0, config.channels, config.buffer_size
+            );
+        }
+    }
+
+    // Scales the drawn scope so the loudest visible sample fills its lane.
+    // Display only; `Output::set_normalize` changes what is heard.
+    pub fn set_auto_fit(&mut self, enabled: bool) {
+        self.auto_fit = enabled;
+    }
+
+    // Fixed display reference when auto-fit is off: a sample of this linear
+    // amplitude reaches the top of its lane, whatever the file. 1.0 is full
+    // scale. Display only.
+    pub fn set_amplitude_ceiling(&mut self, ceiling: f32) {
+        if !ceiling.is_nan() {
+            self.amplitude_ceiling = ceiling.max(MIN_AMPLITUDE_CEILING);
+        }
+    }
+
+    // As `set_amplitude_ceiling`, in dBFS.
+    pub fn set_amplitude_ceiling_db(&mut self, db: f32) {
+        self.set_amplitude_ceiling(10f32.powf(db / 20.0));
+    }
+
+    pub fn amplitude_ceiling(&self) -> f32 {
+        self.amplitude_ceiling
+    }
+
+    // When not following, the trace holds the last frame while audio keeps playing.
+    pub fn set_follow_playhead(&mut self, enabled: bool) {
+        self.follow_playhead = enabled;
+    }
+
+    pub fn follow_playhead(&self) -> bool {
+        self.follow_playhead
+    }
+
+    pub fn set_peak_decay(&mut self, decay_db_per_sec: f32) {
+        self.peak_decay = decay_db_per_sec;
+        for meter in self
+            .meters
+            .iter_mut()
+            .chain(self.mid_side_meters.iter_mut())
+        {
+            meter.set_decay(decay_db_per_sec);
+        }
+    }
+
+    pub fn meters(&self) -> &[PeakHold] {
+        &self.meters
+    }
+
+    // Mid then side, for stereo sources only; empty otherwise.
+    pub fn mid_side_meters(&self) -> &[PeakHold] {
+        &self.mid_side_meters
+    }
+
+    // Draws the mid and side meters as two bars beside the peak ticks.
+    pub fn set_mid_side(&mut self, enabled: bool) {
+        self.mid_side = enabled;
+    }
+
+    pub fn set_visible_samples(&mut self, visible_samples: usize) {
+        self.visible_samples = visible_samples.clamp(2, MAX_TRACE_VERTICES);
+    }
+
+    pub fn visible_samples(&self) -> usize {
+        self.visible_samples
+    }
+
+    // Draws the most recent `visible_samples` of a normalized signal as a
+    // continuous line across the full width, independent of the point display.
+    pub fn set_trace(&mut self, samples: &[f32]) {
+        let visible = self.visible_samples;
+        let samples = match find_trigger(samples, visible, self.trigger, self.trigger_level) {
+            Some(start) => &samples[start..start + visible],
+            None => samples,
+        };
+
+        let trace = generate_trace(samples, visible);
+
+        self.num_trace_vertices = trace.len() as u32;
+        self.queue
+            .write_buffer(&self.trace_buffer, 0, bytemuck::cast_slice(&trace));
+    }
+
+    // Folds stereo into one full-height lane using the mono display mode.
+    pub fn set_single_lane(&mut self, enabled: bool) {
+        self.single_lane = enabled;
+    }
+
+    // Draws the single lane as its envelope mirrored about the center line.
+    pub fn set_symmetric(&mut self, enabled: bool) {
+        self.symmetric = enabled;
+    }
+
+    // Zero line (clip space) for each scope lane, in lane order. Empty keeps
+    // the default split.
+    pub fn set_lane_baselines(&mut self, baselines: Vec<f32>) {
+        self.baselines = baselines;
+    }
+
+    // RGB for each scope lane, in lane order. Lanes without a color are
+    // white; spectral coloring replaces these while it's on.
+    pub fn set_lane_colors(&mut self, colors: Vec<[f32; 3]>) {
+        self.lane_colors = colors;
+    }
+
+    pub fn set_mono_display(&mut self, mode: MonoMode) {
+        self.mono_display = mode;
+    }
+
+    // Seconds of signal across the scope width, limited to what the ring
+    // buffer holds.
+    pub fn set_oscilloscope_range(&mut self, seconds: f32) {
+        if seconds.is_nan() {
+            return;
+        }
+        self.oscilloscope_range = seconds.max(MIN_OSCILLOSCOPE_RANGE);
+    }
+
+    pub fn oscilloscope_range(&self) -> f32 {
+        self.visible_frames() as f32 / self.output.sample_rate.0.max(1) as f32
+    }
+
+    // Track time at the left and right edges of the last drawn scope window,
+    // including any trigger offset.
+    pub fn view(&self) -> ViewTransform {
+        self.view
+    }
+
+    // Called from `update` whenever the drawn window moves or changes size,
+    // with the new view and the number of frames drawn, so overlays drawn
+    // elsewhere can follow it.
+    pub fn set_view_observer(&mut self, observer: impl FnMut(ViewTransform, usize) + 'static) {
+        self.view_observer = Some(Box::new(observer));
+    }
+
+    pub fn visible_frames(&self) -> usize {
+        let capacity = RING_BUFFER_SIZE / self.output.channels.max(1) as usize;
+        let frames = (self.oscilloscope_range * self.output.sample_rate.0 as f32).round();
+
+        (frames as usize).clamp(2, capacity)
+    }
+
+    // Draws new samples at a write head that wraps across the scope instead of
+    // scrolling. Ignored while the trigger is enabled.
+    pub fn set_sweep(&mut self, enabled: bool) {
+        self.sweep = enabled;
+    }
+
+    pub fn sweep(&self) -> bool {
+        self.sweep
+    }
+
+    // Shades the scope by short-term loudness instead of drawing it at full
+    // brightness.
+    pub fn set_energy_coloring(&mut self, enabled: bool) {
+        self.energy_coloring = enabled;
+    }
+
+    // Colors the scope by low/mid/high energy. Combines with energy shading,
+    // which then sets the brightness.
+    pub fn set_spectral_coloring(&mut self, enabled: bool) {
+        self.spectral_coloring = enabled;
+    }
+
+    // Draws each lane's short-term RMS level as a darker band about its zero
+    // line, behind the trace.
+    pub fn set_rms_band(&mut self, enabled: bool) {
+        self.rms_band = enabled;
+    }
+
+    // Starts the scope and trace windows at the first crossing of `level`
+    // (-1..1) so periodic signals hold still. Free-runs when no crossing is
+    // found.
+    pub fn set_trigger(&mut self, mode: TriggerMode, level: f32) {
+        self.trigger = mode;
+        if !level.is_nan() {
+            self.trigger_level = level.clamp(-1.0, 1.0);
+        }
+    }
+
+    pub fn trigger(&self) -> (TriggerMode, f32) {
+        (self.trigger, self.trigger_level)
+    }
+
+    // Pauses while playing, otherwise plays. A finished track starts over.
+    pub fn toggle_playback(&mut self) {
+        let finished = self.output.is_finished();
+        if self.output.is_playing() && !finished {
+            self.playing = false;
+            self.output.pause();
+            return;
+        }
+
+        if finished {
+            let start = if self.output.reverse() {
+                self.output.duration_secs()
+            } else {
+                0.0
+            };
+            self.output.set_position(start);
+        }
+
+        self.playing = true;
+        self.output.play();
+    }
+
+    pub fn set_time_display(&mut self, mode: TimeDisplay) {
+        self.time_display = mode;
+    }
+
+    // Steps are rounded to whole percent so repeated presses land on 5% marks.
+    pub fn change_volume(&mut self, delta: f32) {
+        let volume = ((self.output.volume() + delta) * 100.0).round() / 100.0;
+        self.output.set_volume(volume);
+        self.volume_shown = VOLUME_DISPLAY_SECS;
+    }
+
+    fn generate_volume(&self) -> Vec<Vertex> {
+        let volume = self.output.volume();
+        let intensity = (2.0 * self.volume_shown / VOLUME_DISPLAY_SECS).min(1.0);
+
+        let mut overlay = generate_volume_bar(volume / MAX_VOLUME, 1.0 / MAX_VOLUME);
+
+        let label = format!("VOL {:.0}%", volume * 100.0);
+        let pixel_x = 2.0 / self.size.width.max(1) as f32;
+        let width = (label.len() * ADVANCE) as f32 * HUD_SCALE as f32 * pixel_x;
+        overlay.extend(generate_text(
+            &label,
+            [-width / 2.0, -0.7],
+            HUD_SCALE,
+            self.size.width,
+            self.size.height,
+        ));
+
+        dim(&mut overlay, intensity);
+        overlay
+    }
+
+    // Stops playback and waits for any in-flight decode so no stream or
+    // thread outlives the window.
+    pub fn shutdown(&mut self) {
+        log::info!("Shutting down");
+
+        self.playing = false;
+        self.pending.clear();
+        self.output.stop();
+        log::info!("Output stream stopped");
+
+        for loading in self
+            .loading
+            .take()
+            .into_iter()
+            .chain(self.superseded.drain(..))
+        {
+            if let Err(e) = loading.join() {
+                log::warn!("Discarded load failed: {}", e);
+            }
+            log::info!("Decode thread joined");
+        }
+    }
+
+    fn open_input(&mut self, name: Option<&str>) {
+        match self.output.open_input(name) {
+            Ok(()) => self.playing = true,
+            Err(e) => log::error!("Failed to open input: {}", e),
+        }
+    }
+
+    // Loads `path` straight away when nothing else is, otherwise queues it
+    // behind the current track.
+    fn enqueue(&mut self, path: PathBuf, autoplay: bool) {
+        if !self.output.is_loaded() && self.loading.is_none() {
+            self.load(path, autoplay);
+        } else {
+            self.pending.push_back(path);
+        }
+    }
+
+    fn load(&mut self, path: PathBuf, autoplay: bool) {
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                log::error!("Failed to open {}: {}", path.display(), e);
+                return;
+            }
+        };
+
+        // A load still in flight is superseded: its file goes back to the
+        // front of the queue and its thread is joined at shutdown.
+        if let Some(loading) = self.loading.take() {
+            self.superseded.push(loading);
+            if let Some(previous) = self.current.take() {
+                self.pending.push_front(previous);
+            }
+        }
+
+        self.loading = Some(self.output.start_load(file));
+        self.autoplay = autoplay;
+        self.cue = self.find_cue(&path);
+        self.current = Some(path);
+    }
+
+    // Picks up `name.cue` next to `name.mp3` (or `.wav`) if there is one.
+    fn find_cue(&self, path: &std::path::Path) -> Vec<CueTrack> {
+        let cue_path = path.with_extension("cue");
+        if !cue_path.is_file() {
+            return vec![];
+        }
+
+        load_cue(&cue_path, path).unwrap_or_else(|e| {
+            log::warn!("Ignoring {}: {}", cue_path.display(), e);
+            vec![]
+        })
+    }
+
+    // Index of the cue track the playhead is in.
+    pub fn current_cue(&self) -> Option<usize> {
+        let seconds = self.output.position_secs();
+        self.cue
+            .iter()
+            .rposition(|track| track.start.as_secs_f64() <= seconds)
+    }
+
+    // Jumps `offset` tracks along the cue sheet from the current one.
+    pub fn skip_cue(&mut self, offset: isize) {
+        if self.cue.is_empty() {
+            return;
+        }
+
+        let target = match self.current_cue() {
+            Some(current) => current.saturating_add_signed(offset),
+            None if offset > 0 => offset as usize - 1,
+            None => 0,
+        }
+        .min(self.cue.len() - 1);
+
+        self.output
+            .set_position(self.cue[target].start.as_secs_f64());
+    }
+
+    // Shows the queue over the scope. While it's open Up/Down select, Enter
+    // plays the selection, and dragging a row reorders it.
+    pub fn set_playlist(&mut self, visible: bool) {
+        self.playlist = visible;
+        self.drag = None;
+    }
+
+    pub fn move_item(&mut self, from: usize, to: usize) {
+        if from >= self.pending.len() || to >= self.pending.len() {
+            return;
+        }
+
+        if let Some(path) = self.pending.remove(from) {
+            self.pending.insert(to, path);
+        }
+        self.selected = to;
+    }
+
+    // Skips straight to a queued file, leaving the rest of the queue as is.
+    pub fn play_item(&mut self, index: usize) {
+        if let Some(path) = self.pending.remove(index) {
+            self.load(path, true);
+        }
+        self.selected = self.selected.min(self.pending.len().saturating_sub(1));
+    }
+
+    fn select(&mut self, offset: isize) {
+        let last = self.pending.len().saturating_sub(1) as isize;
+        self.selected = (self.selected as isize + offset).clamp(0, last) as usize;
+    }
+
+    fn playlist_line_height(&self) -> f32 {
+        2.0 * (LINE_HEIGHT as u32 * HUD_SCALE) as f32 / self.size.height.max(1) as f32
+    }
+
+    fn playlist_top(&self) -> f32 {
+        1.0 - 2.0 * 8.0 / self.size.height.max(1) as f32
+    }
+
+    // Queue index under clip-space `y`. The first line is the current file.
+    fn playlist_row(&self, y: f32) -> Option<usize> {
+        let line = ((self.playlist_top() - y) / self.playlist_line_height()).floor();
+        if line < 1.0 {
+            return None;
+        }
+
+        let index = line as usize - 1;
+        (index < self.pending.len()).then_some(index)
+    }
+
+    fn generate_playlist(&self) -> Vec<Vertex> {
+        let name = |path: &PathBuf| {
+            path.file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        };
+
+        let now = self.current.as_ref().map(name).unwrap_or_default();
+        let lines = iter::once(format!("NOW {}", now)).chain(self.pending.iter().enumerate().map(
+            |(i, path)| {
+                let marker = if i == self.selected { ">" } else { " " };
+                format!("{}{} {}", marker, i + 1, name(path))
+            },
+        ));
+
+        let left = -1.0 + 2.0 * 8.0 / self.size.width.max(1) as f32;
+
+        lines
+            .enumerate()
+            .flat_map(|(i, line)| {
+                generate_text(
+                    &line,
+                    [
+                        left,
+                        self.playlist_top() - i as f32 * self.playlist_line_height(),
+                    ],
+                    HUD_SCALE,
+                    self.size.width,
+                    self.size.height,
+                )
+            })
+            .collect()
+    }
+
+    fn advance_queue(&mut self) {
+        if !self.playing || self.loading.is_some() || !self.output.is_finished() {
+            return;
+        }
+
+        if let Some(path) = self.pending.pop_front() {
+            self.load(path, true);
+        }
+    }
+
+    fn title(&self) -> String {
+        match self.pending.len() {
+            0 => self.title.clone(),
+            queued => format!("{} ({} queued)", self.title, queued),
+        }
+    }
+
     fn input(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor = Some([
+                    2.0 * position.x as f32 / self.size.width.max(1) as f32 - 1.0,
+                    1.0 - 2.0 * position.y as f32 / self.size.height.max(1) as f32,
+                ]);
+            }
+            WindowEvent::CursorLeft { .. } => self.cursor = None,
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Left,
+                ..
+            } if self.playlist => {
+                let row = self.cursor.and_then(|[_, y]| self.playlist_row(y));
+                match (state, self.drag.take(), row) {
+                    (ElementState::Pressed, _, Some(row)) => self.drag = Some(row),
+                    (ElementState::Released, Some(from), Some(to)) if from == to => {
+                        self.play_item(from)
+                    }
+                    (ElementState::Released, Some(from), Some(to)) => self.move_item(from, to),
+                    _ => return false,
+                }
+                return true;
+            }
+            _ => {}
+        }
+
         false
     }
 
-    fn update(&mut self, _dt: std::time::Duration) {
+    pub fn update(&mut self, dt: std::time::Duration) {
+        self.poll_loading();
+        self.output.poll();
+
+        let mut overlay = vec![];
+
+        if let Some(loading) = &self.loading {
+            overlay.extend(generate_progress_bar(loading.progress()));
+        }
+
         if self.playing {
-            let data = self.output.buffer_data_dasp();
+            let (data, position) = self.output.snapshot();
 
-            let vertecies = generate_vertexes(&data[0..data.len()], self.output.channels as i16);
+            self.update_meters(&data, dt);
 
-            let mut chan_data = vec![];
+            let held: Vec<f32> = self.meters.iter().map(|meter| meter.held()).collect();
+            if self.single_lane {
+                overlay.extend(generate_peak_ticks(&[held
+                    .iter()
+                    .cloned()
+                    .fold(0.0, f32::max)]));
+            } else {
+                overlay.extend(generate_peak_ticks(&held));
+            }
 
-            for mut chan in vertecies {
-                chan_data.append(&mut chan);
+            if self.mid_side {
+                let held: Vec<f32> = self
+                    .mid_side_meters
+                    .iter()
+                    .map(|meter| meter.held())
+                    .collect();
+                overlay.extend(generate_level_bars(&held));
             }
 
-            self.queue.write_buffer(
-                &self.vertex_buffer,
-                0,
-                bytemuck::cast_slice(&chan_data[0..chan_data.len()]),
-            );
+            if self.follow_playhead {
+                let scale = if self.auto_fit {
+                    auto_fit_scale(&data)
+                } else {
+                    1.0 / self.amplitude_ceiling
+                };
+
+                let mono = self.single_lane.then_some(self.mono_display);
+
+                let channels = self.output.channels.max(1) as usize;
+                let visible = self.visible_frames();
+                let frames = data.len() / channels;
+
+                let first: Vec<f32> = data
+                    .iter()
+                    .step_by(channels)
+                    .map(|sample| *sample as f32 / 32768.0)
+                    .collect();
+
+                let start = find_trigger(&first, visible, self.trigger, self.trigger_level)
+                    .unwrap_or(frames.saturating_sub(visible));
+
+                let end = (start + visible).min(frames);
+                let window = &data[start * channels..end * channels];
+
+                let rate = self.output.sample_rate.0.max(1) as f64;
+                let oldest = (position / channels) as f64 - frames as f64;
+                let view = ViewTransform::new(
+                    ((oldest + start as f64) / rate).max(0.0),
+                    ((oldest + end as f64) / rate).max(0.0),
+                );
+                if view != self.view {
+                    self.view = view;
+                    if let Some(observer) = self.view_observer.as_mut() {
+                        observer(view, end - start);
+                    }
+                }
+
+                let mut vertecies =
+                    generate_vertexes(window, self.output.channels as i16, scale, mono);
+
+                color_lanes(&mut vertecies, &self.lane_colors);
+
+                if self.spectral_coloring {
+                    tint_by_spectrum(&mut vertecies, window, channels, self.output.sample_rate.0);
+                }
+
+                if self.energy_coloring {
+                    tint_by_energy(&mut vertecies, window, channels);
+                }
+
+                offset_lanes(&mut vertecies, &self.baselines);
+
+                let mut bands = if self.rms_band {
+                    rms_bands(&vertecies, &self.baselines)
+                } else {
+                    vec![]
+                };
+
+                if self.single_lane && self.symmetric {
+                    vertecies = mirror(vertecies, &self.baselines);
+                }
+
+                if self.sweep && self.trigger == TriggerMode::Off {
+                    vertecies = sweep(vertecies, position / channels);
+                    bands = sweep(bands, position / channels);
+                }
+
+                // Bands first so the trace is drawn over them.
+                bands.append(&mut vertecies);
+                self.draw_signal(bands);
+            }
+        }
+
+        if let (Some([x, _]), true, false) = (self.cursor, self.playing, self.playlist) {
+            overlay.extend(generate_cursor_line(x));
+            overlay.extend(self.generate_hover_label(x));
+        }
+
+        if self.volume_shown > 0.0 {
+            overlay.extend(self.generate_volume());
+            self.volume_shown -= dt.as_secs_f32();
+        }
+
+        if dt.as_secs_f32() > 0.0 {
+            self.fps += (1.0 / dt.as_secs_f32() - self.fps) * 0.1;
+        }
+
+        if self.playlist {
+            overlay.extend(self.generate_playlist());
+        } else if self.hud {
+            overlay.extend(self.generate_hud());
+        }
+
+        overlay.truncate(MAX_OVERLAY_VERTICES);
+        self.num_overlay_vertices = overlay.len() as u32;
+        self.queue
+            .write_buffer(&self.overlay_buffer, 0, bytemuck::cast_slice(&overlay));
+    }
+
+    // Each track is drawn as its own line strip so lanes aren't joined.
+    pub fn draw_signal(&mut self, tracks: Vec<Vec<Vertex>>) {
+        let capacity = (self.vertex_buffer.size() / std::mem::size_of::<Vertex>() as u64) as usize;
+
+        let mut chan_data = vec![];
+        self.lanes.clear();
+
+        for mut chan in tracks {
+            chan.truncate(capacity - chan_data.len());
+
+            let start = chan_data.len() as u32;
+            chan_data.append(&mut chan);
+            self.lanes.push(start..chan_data.len() as u32);
+        }
+
+        self.queue.write_buffer(
+            &self.vertex_buffer,
+            0,
+            bytemuck::cast_slice(&chan_data[0..chan_data.len()]),
+        );
+    }
+
+    fn update_meters(&mut self, data: &[i32], dt: std::time::Duration) {
+        let peaks = channel_peaks(data, self.output.channels as usize);
+
+        self.meters
+            .resize_with(peaks.len(), || PeakHold::new(self.peak_decay));
+
+        for (meter, peak) in self.meters.iter_mut().zip(peaks) {
+            meter.update(peak, dt);
+        }
+
+        if self.output.channels != 2 {
+            self.mid_side_meters.clear();
+            return;
+        }
+
+        self.mid_side_meters
+            .resize_with(2, || PeakHold::new(self.peak_decay));
+
+        for (meter, peak) in self.mid_side_meters.iter_mut().zip(mid_side_peaks(data)) {
+            meter.update(peak, dt);
+        }
+    }
+
+    // Time under the cursor, drawn beside the hover line and flipped to its
+    // left near the right edge.
+    fn generate_hover_label(&self, x: f32) -> Vec<Vertex> {
+        let px = (x + 1.0) / 2.0 * self.size.width as f32;
+        let seconds = self.view.x_to_secs(px, self.size.width);
+        let label = format!("{:.3}S", seconds);
+
+        let pixel_x = 2.0 / self.size.width.max(1) as f32;
+        let width = (label.len() * ADVANCE) as f32 * HUD_SCALE as f32 * pixel_x;
+        let gap = 4.0 * pixel_x;
+        let left = if x + gap + width > 1.0 {
+            x - gap - width
+        } else {
+            x + gap
+        };
+        let top = 1.0 - 2.0 * 8.0 / self.size.height.max(1) as f32;
+
+        generate_text(
+            &label,
+            [left, top],
+            HUD_SCALE,
+            self.size.width,
+            self.size.height,
+        )
+    }
+
+    fn generate_hud(&self) -> Vec<Vertex> {
+        let position = *self.output.position.lock().unwrap();
+        let frame = position / self.output.channels.max(1) as usize;
+        let seconds = self.output.position_secs();
+        let duration = self.output.duration_secs();
+
+        let time = match self.time_display {
+            TimeDisplay::Elapsed => format!("POS {}", format_time(seconds)),
+            TimeDisplay::Remaining => {
+                format!("POS -{}", format_time((duration - seconds).max(0.0)))
+            }
+            TimeDisplay::Total => format!("LEN {}", format_time(duration)),
+        };
+
+        let mut lines = vec![
+            format!("FPS {:.0}", self.fps),
+            time,
+            format!("FRAME {}", frame),
+            format!("QUEUE {}", self.pending.len()),
+            format!("SPEED {:.2}X", self.output.speed()),
+        ];
+
+        if let Some(track) = self.current_cue().map(|index| &self.cue[index]) {
+            lines.push(format!(
+                "CUE {:02} {}",
+                track.number,
+                track.title.as_deref().unwrap_or("")
+            ));
+        }
+
+        // Same line spacing and top margin as the playlist.
+        let line_height = self.playlist_line_height();
+        let left = -1.0 + 2.0 * 8.0 / self.size.width.max(1) as f32;
+        let top = self.playlist_top();
+
+        lines
+            .iter()
+            .enumerate()
+            .flat_map(|(i, line)| {
+                generate_text(
+                    line,
+                    [left, top - i as f32 * line_height],
+                    HUD_SCALE,
+                    self.size.width,
+                    self.size.height,
+                )
+            })
+            .collect()
+    }
+
+    fn poll_loading(&mut self) {
+        self.superseded.retain(|loading| !loading.is_finished());
+
+        if !self.loading.as_ref().is_some_and(Loading::is_finished) {
+            return;
+        }
+
+        let loading = self.loading.take().unwrap();
+        if let Err(e) = self.output.finish_load(loading) {
+            log::error!("Failed to load file: {}", e);
+            // Move on to the next file unless a track is still playing, so a
+            // bad file doesn't strand the ones queued behind it.
+            if !self.output.is_loaded() || self.output.is_finished() {
+                while self.loading.is_none() {
+                    let Some(path) = self.pending.pop_front() else {
+                        break;
+                    };
+                    self.load(path, self.autoplay);
+                }
+            }
+            return;
+        }
+
+        self.playing = true;
+        if self.autoplay {
+            self.output.play();
         }
     }
 
-    fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        let output = self.surface.get_current_texture()?;
+    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        let output = match self.surface.get_current_texture() {
+            Ok(output) => output,
+            Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                self.surface.configure(&self.device, &self.config);
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
         let view = output
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
@@ -208,10 +1172,20 @@ impl State {
                 depth_stencil_attachment: None,
             });
 
-            render_pass.set_pipeline(&self.render_pipeline);
+            render_pass.set_pipeline(&self.trace_pipeline);
 
             render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            render_pass.draw(0..self.num_vertices, 0..1);
+            for lane in &self.lanes {
+                render_pass.draw(lane.clone(), 0..1);
+            }
+
+            render_pass.set_vertex_buffer(0, self.trace_buffer.slice(..));
+            render_pass.draw(0..self.num_trace_vertices, 0..1);
+
+            // Overlays (meters, progress, HUD) go last so they stay on top.
+            render_pass.set_pipeline(&self.overlay_pipeline);
+            render_pass.set_vertex_buffer(0, self.overlay_buffer.slice(..));
+            render_pass.draw(0..self.num_overlay_vertices, 0..1);
         }
 
         self.queue.submit(iter::once(encoder.finish()));
@@ -221,84 +1195,226 @@ impl State {
     }
 }
 
-pub async fn run() {
-    env_logger::init();
+fn format_time(seconds: f64) -> String {
+    format!("{}:{:04.1}", (seconds / 60.0) as u32, seconds % 60.0)
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    format: wgpu::TextureFormat,
+    topology: wgpu::PrimitiveTopology,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Render Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[Vertex::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw, // 2.
+            cull_mode: Some(wgpu::Face::Back),
+            polygon_mode: wgpu::PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: None, // 1.
+        multisample: wgpu::MultisampleState {
+            count: 1,                         // 2.
+            mask: !0,                         // 3.
+            alpha_to_coverage_enabled: false, // 4.
+        },
+        multiview: None, // 5.
+    })
+}
+
+// What to scope at startup.
+pub enum Source {
+    File(PathBuf),
+    Input(Option<String>),
+}
 
+pub async fn run(diagnostics: bool, renderer_config: RendererConfig, source: Option<Source>) {
     let event_loop = EventLoop::new();
-    let window = Window::new(&event_loop).unwrap();
+    let mut builder = WindowBuilder::new().with_title(&renderer_config.title);
+    if let Some((width, height)) = renderer_config.window_size {
+        builder = builder.with_inner_size(LogicalSize::new(width, height));
+    }
+    let window = builder.build(&event_loop).unwrap();
+
+    let mut state = State::new(&window, renderer_config).await;
+
+    if diagnostics {
+        state.print_diagnostics();
+        return;
+    }
 
-    let mut state = State::new(&window).await;
+    match source {
+        Some(Source::File(path)) => state.enqueue(path, false),
+        Some(Source::Input(name)) => state.open_input(name.as_deref()),
+        None => {}
+    }
+    let mut last_title = state.title();
+    window.set_title(&last_title);
 
-    let render_start_time = std::time::Instant::now();
+    let mut last_render_time = std::time::Instant::now();
+    let mut minimized = false;
+    let mut occluded = false;
 
     event_loop.run(move |event, _, control_flow| match event {
         Event::WindowEvent {
             ref event,
             window_id,
-        } if window_id == window.id() => {
-            if !state.input(event) {
-                match event {
-                    WindowEvent::CloseRequested
-                    | WindowEvent::KeyboardInput {
-                        input:
-                            KeyboardInput {
-                                state: ElementState::Pressed,
-                                virtual_keycode: Some(VirtualKeyCode::Escape),
-                                ..
-                            },
+        } if window_id == window.id() && !state.input(event) => match event {
+            WindowEvent::CloseRequested
+            | WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::Escape),
                         ..
-                    } => *control_flow = ControlFlow::Exit,
-                    WindowEvent::Resized(physical_size) => {
-                        state.resize(*physical_size);
-                    }
-                    WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-                        state.resize(**new_inner_size);
-                    }
-                    WindowEvent::DroppedFile(path_buf) => {
-                        let file = File::open(path_buf.as_os_str()).unwrap();
-                        state.output.load_file(file);
-                        state.playing = true;
-                    }
-                    WindowEvent::KeyboardInput {
-                        input:
-                            KeyboardInput {
-                                virtual_keycode: Some(keycode),
-                                ..
-                            },
+                    },
+                ..
+            } => *control_flow = ControlFlow::Exit,
+            WindowEvent::Resized(physical_size) => {
+                minimized = physical_size.width == 0 || physical_size.height == 0;
+                state.resize(*physical_size);
+            }
+            WindowEvent::Occluded(hidden) => occluded = *hidden,
+            WindowEvent::Focused(true) => occluded = false,
+            WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                state.resize(**new_inner_size);
+            }
+            WindowEvent::DroppedFile(path_buf) => state.enqueue(path_buf.clone(), true),
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(keycode),
                         ..
-                    } => match keycode {
-                        VirtualKeyCode::Space => {
-                            state.playing = true;
-                            state.output.play()
-                        }
-                        VirtualKeyCode::Up => state.output.forward(1 as f64),
-                        VirtualKeyCode::P => {
-                            state.playing = false;
-                            state.output.pause()
-                        }
-                        _ => {}
                     },
-                    _ => {}
+                ..
+            } => match keycode {
+                VirtualKeyCode::Tab => state.set_playlist(!state.playlist),
+                VirtualKeyCode::Up if state.playlist => state.select(-1),
+                VirtualKeyCode::Down if state.playlist => state.select(1),
+                VirtualKeyCode::Return if state.playlist => state.play_item(state.selected),
+                VirtualKeyCode::Space => state.toggle_playback(),
+                VirtualKeyCode::Equals | VirtualKeyCode::Plus | VirtualKeyCode::NumpadAdd => {
+                    state.change_volume(VOLUME_STEP)
                 }
-            }
-        }
+                VirtualKeyCode::Minus | VirtualKeyCode::NumpadSubtract => {
+                    state.change_volume(-VOLUME_STEP)
+                }
+                VirtualKeyCode::PageDown => state.skip_cue(1),
+                VirtualKeyCode::PageUp => state.skip_cue(-1),
+                VirtualKeyCode::Up => state.output.forward(1.0),
+                VirtualKeyCode::Down => state.output.backward(1.0),
+                VirtualKeyCode::A => state.set_auto_fit(!state.auto_fit),
+                VirtualKeyCode::F => state.set_follow_playhead(!state.follow_playhead()),
+                VirtualKeyCode::C => {
+                    let enabled = !state.output.crossfeed();
+                    state.output.set_crossfeed(enabled)
+                }
+                VirtualKeyCode::N => {
+                    let enabled = !state.output.normalize();
+                    state.output.set_normalize(enabled)
+                }
+                VirtualKeyCode::M => state.output.toggle_mute(),
+                VirtualKeyCode::LBracket => {
+                    let speed = state.output.speed() - SPEED_STEP;
+                    state.output.set_speed(speed)
+                }
+                VirtualKeyCode::RBracket => {
+                    let speed = state.output.speed() + SPEED_STEP;
+                    state.output.set_speed(speed)
+                }
+                VirtualKeyCode::L => {
+                    let enabled = !state.output.is_looping();
+                    state.output.set_loop(enabled)
+                }
+                VirtualKeyCode::R => {
+                    let enabled = !state.output.reverse();
+                    state.output.set_reverse(enabled)
+                }
+                VirtualKeyCode::S => state.set_single_lane(!state.single_lane),
+                VirtualKeyCode::V => state.set_mid_side(!state.mid_side),
+                VirtualKeyCode::Y => state.set_symmetric(!state.symmetric),
+                VirtualKeyCode::E => state.set_rms_band(!state.rms_band),
+                VirtualKeyCode::G => state.set_energy_coloring(!state.energy_coloring),
+                VirtualKeyCode::B => state.set_spectral_coloring(!state.spectral_coloring),
+                VirtualKeyCode::W => state.set_sweep(!state.sweep),
+                VirtualKeyCode::T => {
+                    let mode = match state.trigger {
+                        TriggerMode::Off => TriggerMode::RisingEdge,
+                        TriggerMode::RisingEdge => TriggerMode::FallingEdge,
+                        TriggerMode::FallingEdge => TriggerMode::Off,
+                    };
+                    state.set_trigger(mode, state.trigger_level)
+                }
+                VirtualKeyCode::F3 => state.hud = !state.hud,
+                VirtualKeyCode::D => state.set_time_display(state.time_display.next()),
+                VirtualKeyCode::Comma => {
+                    state.set_oscilloscope_range(state.oscilloscope_range() / 2.0)
+                }
+                VirtualKeyCode::Period => {
+                    state.set_oscilloscope_range(state.oscilloscope_range() * 2.0)
+                }
+                VirtualKeyCode::P => {
+                    state.playing = false;
+                    state.output.pause()
+                }
+                _ => {}
+            },
+            _ => {}
+        },
         Event::RedrawRequested(window_id) if window_id == window.id() => {
             let now = std::time::Instant::now();
-            let dt = now - render_start_time;
+            let dt = now - last_render_time;
+            last_render_time = now;
+            state.advance_queue();
+            let title = state.title();
+            if title != last_title {
+                window.set_title(&title);
+                last_title = title;
+            }
             state.update(dt);
             match state.render() {
                 Ok(_) => {}
-                Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
-                    state.resize(state.size)
-                }
                 Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
-
                 Err(wgpu::SurfaceError::Timeout) => log::warn!("Surface timeout"),
+                Err(e) => log::warn!("Surface error: {:?}", e),
             }
         }
+        // Nothing is visible while minimized or covered, so skip drawing and
+        // only wake often enough to keep loads and the queue moving. Audio
+        // runs on its own thread.
+        Event::RedrawEventsCleared if minimized || occluded => {
+            state.poll_loading();
+            state.output.poll();
+            state.advance_queue();
+            *control_flow =
+                ControlFlow::WaitUntil(std::time::Instant::now() + BACKGROUND_POLL_INTERVAL);
+        }
         Event::RedrawEventsCleared => {
+            *control_flow = ControlFlow::Poll;
             window.request_redraw();
         }
+        Event::LoopDestroyed => state.shutdown(),
         _ => {}
     });
 }
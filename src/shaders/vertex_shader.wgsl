@@ -2,6 +2,7 @@
 
 struct VertexInput {
     @location(0) position: vec2<f32>,
+    @location(1) color: vec3<f32>,
 };
 
 struct VertexOutput {
@@ -16,13 +17,14 @@ fn vs_main(
 ) -> VertexOutput {
     var out: VertexOutput;
     out.clip_position = vec4<f32>(model.position, 0.0, 1.0);
+    out.color = model.color;
     return out;
 }
 
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return vec4<f32>(1.0,1.0,1.0,1.0);
+    return vec4<f32>(in.color, 1.0);
 }
 
  
\ No newline at end of file
@@ -0,0 +1,203 @@
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::Stream;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::output::Playback;
+
+// Where `Output` sends its samples. The device pulls from the `Playback`
+// callback at its own pace.
+pub trait OutputSink {
+    fn open(
+        &mut self,
+        sample_rate: cpal::SampleRate,
+        channels: cpal::ChannelCount,
+        buffer_frames: Option<u32>,
+        playback: Playback,
+    ) -> anyhow::Result<cpal::StreamConfig>;
+    fn close(&mut self);
+    fn is_open(&self) -> bool;
+    fn play(&mut self);
+    fn pause(&mut self);
+}
+
+#[derive(Default)]
+pub struct CpalSink {
+    stream: Option<Stream>,
+}
+
+impl OutputSink for CpalSink {
+    fn open(
+        &mut self,
+        sample_rate: cpal::SampleRate,
+        channels: cpal::ChannelCount,
+        buffer_frames: Option<u32>,
+        mut playback: Playback,
+    ) -> anyhow::Result<cpal::StreamConfig> {
+        self.stream = None;
+
+        let host = cpal::default_host();
+
+        let device = host
+            .default_output_device()
+            .ok_or_else(|| anyhow::anyhow!("No output device available"))?;
+
+        let mut supported_configs_range = device.supported_output_configs()?;
+
+        let supported_config = supported_configs_range
+            .find(|range| {
+                range.sample_format() == cpal::SampleFormat::F32
+                    && range.max_sample_rate() >= sample_rate
+                    && range.min_sample_rate() <= sample_rate
+                    && range.channels() == channels
+            })
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "No f32 output config for {} Hz, {} channels",
+                    sample_rate.0,
+                    channels
+                )
+            })?
+            .with_sample_rate(sample_rate);
+
+        let supported_buffer_size = supported_config.buffer_size().clone();
+        let mut config: cpal::StreamConfig = supported_config.into();
+
+        if let Some(frames) = buffer_frames {
+            match supported_buffer_size {
+                cpal::SupportedBufferSize::Range { min, max } if (min..=max).contains(&frames) => {
+                    config.buffer_size = cpal::BufferSize::Fixed(frames)
+                }
+                supported => log::warn!(
+                    "Buffer size of {} frames not supported ({:?}), using the device default",
+                    frames,
+                    supported
+                ),
+            }
+        }
+
+        let samples_per_second = sample_rate.0.max(1) as f64 * channels.max(1) as f64;
+
+        self.stream = Some(device.build_output_stream(
+            &config,
+            move |data: &mut [f32], info: &cpal::OutputCallbackInfo| {
+                // The device reports when this buffer's first sample
+                // plays; the position ends up past its last one.
+                let timestamp = info.timestamp();
+                if let Some(delay) = timestamp.playback.duration_since(&timestamp.callback) {
+                    let buffered = data.len() as f64 / samples_per_second;
+                    playback.set_latency(delay + Duration::from_secs_f64(buffered));
+                }
+                playback.fill(data)
+            },
+            move |err| log::error!("Output stream error: {}", err),
+        )?);
+
+        Ok(config)
+    }
+
+    fn close(&mut self) {
+        self.stream = None;
+    }
+
+    fn is_open(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    fn play(&mut self) {
+        if let Some(ref stream) = self.stream {
+            if let Err(e) = stream.play() {
+                log::error!("Failed to start output stream: {}", e);
+            }
+        }
+    }
+
+    fn pause(&mut self) {
+        if let Some(ref stream) = self.stream {
+            if let Err(e) = stream.pause() {
+                log::error!("Failed to pause output stream: {}", e);
+            }
+        }
+    }
+}
+
+// Records what a device would have played, for driving `Output` without
+// audio hardware. Clones share the same recording, so keep one to inspect
+// after handing another to `Output::set_output`.
+#[derive(Clone, Default)]
+pub struct RecordingSink {
+    inner: Arc<Mutex<Recording>>,
+}
+
+#[derive(Default)]
+struct Recording {
+    playback: Option<Playback>,
+    channels: usize,
+    playing: bool,
+    samples: Vec<f32>,
+}
+
+impl RecordingSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Runs the callback for `frames` frames, as a device would while playing.
+    pub fn pull(&self, frames: usize) {
+        let mut recording = self.inner.lock().unwrap();
+        let recording = &mut *recording;
+
+        if let (Some(playback), true) = (recording.playback.as_mut(), recording.playing) {
+            let mut data = vec![0.0; frames * recording.channels];
+            playback.fill(&mut data);
+            recording.samples.extend(data);
+        }
+    }
+
+    pub fn samples(&self) -> Vec<f32> {
+        self.inner.lock().unwrap().samples.clone()
+    }
+
+    pub fn clear(&self) {
+        self.inner.lock().unwrap().samples.clear();
+    }
+}
+
+impl OutputSink for RecordingSink {
+    fn open(
+        &mut self,
+        sample_rate: cpal::SampleRate,
+        channels: cpal::ChannelCount,
+        buffer_frames: Option<u32>,
+        playback: Playback,
+    ) -> anyhow::Result<cpal::StreamConfig> {
+        let mut recording = self.inner.lock().unwrap();
+        recording.playback = Some(playback);
+        recording.channels = channels as usize;
+        recording.playing = false;
+
+        Ok(cpal::StreamConfig {
+            channels,
+            sample_rate,
+            buffer_size: buffer_frames.map_or(cpal::BufferSize::Default, cpal::BufferSize::Fixed),
+        })
+    }
+
+    fn close(&mut self) {
+        let mut recording = self.inner.lock().unwrap();
+        recording.playback = None;
+        recording.playing = false;
+    }
+
+    fn is_open(&self) -> bool {
+        self.inner.lock().unwrap().playback.is_some()
+    }
+
+    fn play(&mut self) {
+        self.inner.lock().unwrap().playing = true;
+    }
+
+    fn pause(&mut self) {
+        self.inner.lock().unwrap().playing = false;
+    }
+}
@@ -1,15 +1,25 @@
 use bytemuck::{Pod, Zeroable};
 use dasp::signal::{self as signal, Signal};
 
+use crate::dsp::one_pole_coefficient;
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Pod, Zeroable)]
 pub struct Vertex {
     position: [f32; 2],
+    color: [f32; 3],
 }
 
 impl Vertex {
+    pub fn new(position: [f32; 2]) -> Self {
+        Self {
+            position,
+            color: [1.0; 3],
+        }
+    }
+
     const ATTRIBS: [wgpu::VertexAttribute; 2] =
-        wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3];
+        wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x3];
 
     pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
         use std::mem;
@@ -33,19 +43,82 @@ const OUTPUT_END_LEFT: f32 = 0.0;
 const OUTPUT_START_RIGHT: f32 = 0.0;
 const OUTPUT_END_RIGHT: f32 = 1.0;
 
+const TICK_START: f32 = 0.95;
+const TICK_END: f32 = 1.0;
+
+const LEVEL_BAR_RIGHT: f32 = 0.93;
+const LEVEL_BAR_WIDTH: f32 = 0.02;
+const LEVEL_BAR_GAP: f32 = 0.01;
+const LEVEL_BAR_COLUMNS: usize = 4;
+
+const PROGRESS_LEFT: f32 = -0.5;
+const PROGRESS_RIGHT: f32 = 0.5;
+const PROGRESS_HALF_HEIGHT: f32 = 0.05;
+const PROGRESS_ROWS: usize = 16;
+
+const VOLUME_TOP: f32 = -0.8;
+const VOLUME_BOTTOM: f32 = -0.85;
+const VOLUME_ROWS: usize = 6;
+
+const ENERGY_BLOCK: usize = 64;
+const ENERGY_FLOOR_DB: f32 = -60.0;
+const MIN_INTENSITY: f32 = 0.2;
+const LOW_BAND_HZ: f32 = 200.0;
+const HIGH_BAND_HZ: f32 = 2000.0;
+const RMS_BLOCK: usize = 32;
+const RMS_SHADE: f32 = 0.4;
+
+// Quietest peak auto-fit will scale up to full height, about -60 dBFS.
+const MIN_FIT_PEAK: f32 = 32.0;
+
 struct Sample {
     left: i32,
     right: i32,
 }
 
-pub fn generate_vertexes(ring_buffer: &[i32], channels: i16) -> Vec<Vec<Vertex>> {
+// How a stereo signal is folded into a single lane. `Sum` can cancel
+// out-of-phase content, so `Max` is the default.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum MonoMode {
+    Sum,
+    #[default]
+    Max,
+    Left,
+}
+
+impl MonoMode {
+    fn collapse(self, left: i32, right: i32) -> i32 {
+        match self {
+            MonoMode::Sum => (left + right) / 2,
+            MonoMode::Max if left.abs() >= right.abs() => left,
+            MonoMode::Max => right,
+            MonoMode::Left => left,
+        }
+    }
+}
+
+pub fn auto_fit_scale(ring_buffer: &[i32]) -> f32 {
+    let peak = ring_buffer
+        .iter()
+        .map(|sample| sample.unsigned_abs())
+        .max()
+        .unwrap_or(0) as f32;
+
+    INPUT_END / peak.max(MIN_FIT_PEAK)
+}
+
+pub fn generate_vertexes(
+    ring_buffer: &[i32],
+    channels: i16,
+    scale: f32,
+    mono: Option<MonoMode>,
+) -> Vec<Vec<Vertex>> {
     let mut return_vec: Vec<Vec<Vertex>> = vec![];
 
     match channels {
         1 => {
             let signal: Vec<i32> = signal::from_iter(ring_buffer.iter().cloned())
                 .until_exhausted()
-                .map(|sample| sample)
                 .collect();
 
             let mut mono_return: Vec<Vertex> = vec![];
@@ -55,13 +128,11 @@ pub fn generate_vertexes(ring_buffer: &[i32], channels: i16) -> Vec<Vec<Vertex>>
 
                 let x: f32 = (2.0 / frac) - 1.0;
 
-                let mono_chan = ((*s as f32 - INPUT_START) / (INPUT_END - INPUT_START))
+                let mono_chan = ((*s as f32 * scale - INPUT_START) / (INPUT_END - INPUT_START))
                     * (OUTPUT_END - OUTPUT_START)
                     + OUTPUT_START;
 
-                mono_return.push(Vertex {
-                    position: [x, mono_chan],
-                });
+                mono_return.push(Vertex::new([x, mono_chan]));
             }
 
             return_vec.push(mono_return);
@@ -74,6 +145,15 @@ pub fn generate_vertexes(ring_buffer: &[i32], channels: i16) -> Vec<Vec<Vertex>>
                     .map(|[left, right]| Sample { left, right })
                     .collect();
 
+            if let Some(mode) = mono {
+                let collapsed: Vec<i32> = signal
+                    .iter()
+                    .map(|s| mode.collapse(s.left, s.right))
+                    .collect();
+
+                return generate_vertexes(&collapsed, 1, scale, None);
+            }
+
             let mut left_vec: Vec<Vertex> = vec![];
             let mut right_vec: Vec<Vertex> = vec![];
 
@@ -82,21 +162,17 @@ pub fn generate_vertexes(ring_buffer: &[i32], channels: i16) -> Vec<Vec<Vertex>>
 
                 let x: f32 = (2.0 / frac) - 1.0;
 
-                let left = ((s.left as f32 - INPUT_START) / (INPUT_END - INPUT_START))
+                let left = ((s.left as f32 * scale - INPUT_START) / (INPUT_END - INPUT_START))
                     * (OUTPUT_END_LEFT - OUTPUT_START_LEFT)
                     + OUTPUT_START_LEFT;
 
-                let right = ((s.right as f32 - INPUT_START) / (INPUT_END - INPUT_START))
+                let right = ((s.right as f32 * scale - INPUT_START) / (INPUT_END - INPUT_START))
                     * (OUTPUT_END_RIGHT - OUTPUT_START_RIGHT)
                     + OUTPUT_START_RIGHT;
 
-                left_vec.push(Vertex {
-                    position: [x, left],
-                });
+                left_vec.push(Vertex::new([x, left]));
 
-                right_vec.push(Vertex {
-                    position: [x, right],
-                });
+                right_vec.push(Vertex::new([x, right]));
             }
 
             return_vec.push(left_vec);
@@ -107,3 +183,399 @@ pub fn generate_vertexes(ring_buffer: &[i32], channels: i16) -> Vec<Vec<Vertex>>
         _ => return_vec,
     }
 }
+
+// Colors lane `i` with `colors[i]`; lanes without one stay white.
+pub fn color_lanes(tracks: &mut [Vec<Vertex>], colors: &[[f32; 3]]) {
+    for (track, color) in tracks.iter_mut().zip(colors) {
+        for vertex in track.iter_mut() {
+            vertex.color = *color;
+        }
+    }
+}
+
+// Dims each block of `ENERGY_BLOCK` frames by its RMS level, so sustained
+// loud passages glow and quiet ones fade regardless of their peak height.
+// `ring_buffer` must be the interleaved window the tracks were built from.
+pub fn tint_by_energy(tracks: &mut [Vec<Vertex>], ring_buffer: &[i32], channels: usize) {
+    let block_len = ENERGY_BLOCK * channels.max(1);
+
+    let intensities: Vec<f32> = ring_buffer
+        .chunks(block_len)
+        .map(|block| {
+            let mean_square = block
+                .iter()
+                .map(|sample| (*sample as f32 / 32768.0).powi(2))
+                .sum::<f32>()
+                / block.len() as f32;
+            let db = (10.0 * mean_square.log10()).max(ENERGY_FLOOR_DB);
+
+            MIN_INTENSITY + (1.0 - MIN_INTENSITY) * (1.0 - db / ENERGY_FLOOR_DB)
+        })
+        .collect();
+
+    for track in tracks.iter_mut() {
+        for (frame, vertex) in track.iter_mut().enumerate() {
+            if let Some(intensity) = intensities.get(frame / ENERGY_BLOCK) {
+                vertex.color = vertex.color.map(|channel| channel * intensity);
+            }
+        }
+    }
+}
+
+// Colors each block of `ENERGY_BLOCK` frames by where its energy sits:
+// red for lows, green for mids and blue for highs, split with one-pole
+// filters at `LOW_BAND_HZ` and `HIGH_BAND_HZ`.
+pub fn tint_by_spectrum(
+    tracks: &mut [Vec<Vertex>],
+    ring_buffer: &[i32],
+    channels: usize,
+    sample_rate: u32,
+) {
+    let channels = channels.max(1);
+    let low_coefficient = one_pole_coefficient(LOW_BAND_HZ, sample_rate as f32);
+    let high_coefficient = one_pole_coefficient(HIGH_BAND_HZ, sample_rate as f32);
+    let (mut below_low, mut below_high) = (0.0, 0.0);
+
+    let colors: Vec<[f32; 3]> = ring_buffer
+        .chunks(ENERGY_BLOCK * channels)
+        .map(|block| {
+            let mut bands = [0.0f32; 3];
+
+            for frame in block.chunks(channels) {
+                let x = frame.iter().map(|sample| *sample as f32).sum::<f32>()
+                    / (channels as f32 * 32768.0);
+
+                below_low += low_coefficient * (x - below_low);
+                below_high += high_coefficient * (x - below_high);
+
+                bands[0] += below_low * below_low;
+                bands[1] += (below_high - below_low).powi(2);
+                bands[2] += (x - below_high).powi(2);
+            }
+
+            let bands = bands.map(f32::sqrt);
+            let loudest = bands.iter().cloned().fold(0.0, f32::max);
+            if loudest > 0.0 {
+                bands.map(|band| band / loudest)
+            } else {
+                [1.0; 3]
+            }
+        })
+        .collect();
+
+    for track in tracks.iter_mut() {
+        for (frame, vertex) in track.iter_mut().enumerate() {
+            if let Some(color) = colors.get(frame / ENERGY_BLOCK) {
+                vertex.color = *color;
+            }
+        }
+    }
+}
+
+// Reflects each lane about its zero line so it shows the envelope `|s|` both
+// above and below, instead of the signal itself. `baselines` are as passed to
+// `offset_lanes`.
+pub fn mirror(tracks: Vec<Vec<Vertex>>, baselines: &[f32]) -> Vec<Vec<Vertex>> {
+    let lanes = tracks.len();
+    let mut mirrored = vec![];
+
+    for (channel, track) in tracks.into_iter().enumerate() {
+        let center = baselines
+            .get(channel)
+            .cloned()
+            .unwrap_or_else(|| lane_center(channel, lanes));
+
+        for sign in [1.0, -1.0] {
+            mirrored.push(
+                track
+                    .iter()
+                    .map(|vertex| {
+                        let envelope = (vertex.position[1] - center).abs();
+
+                        Vertex {
+                            position: [vertex.position[0], center + sign * envelope],
+                            ..*vertex
+                        }
+                    })
+                    .collect(),
+            );
+        }
+    }
+
+    mirrored
+}
+
+// The RMS level of each block of `RMS_BLOCK` frames as a band about each
+// lane's zero line, in a darker shade of the lane. Each band is a strip with
+// one vertex per frame zigzagging between the band's edges, so it fills in
+// and `sweep` moves it with the trace. `baselines` are as passed to
+// `offset_lanes`.
+pub fn rms_bands(tracks: &[Vec<Vertex>], baselines: &[f32]) -> Vec<Vec<Vertex>> {
+    let lanes = tracks.len();
+
+    tracks
+        .iter()
+        .enumerate()
+        .map(|(channel, track)| {
+            let center = baselines
+                .get(channel)
+                .cloned()
+                .unwrap_or_else(|| lane_center(channel, lanes));
+
+            track
+                .chunks(RMS_BLOCK)
+                .flat_map(|block| {
+                    let rms = (block
+                        .iter()
+                        .map(|vertex| (vertex.position[1] - center).powi(2))
+                        .sum::<f32>()
+                        / block.len() as f32)
+                        .sqrt();
+
+                    block.iter().enumerate().map(move |(i, vertex)| {
+                        let sign = if i % 2 == 0 { 1.0 } else { -1.0 };
+
+                        Vertex {
+                            position: [vertex.position[0], center + sign * rms],
+                            color: vertex.color.map(|channel| channel * RMS_SHADE),
+                        }
+                    })
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// Moves lane `i`'s zero line to `baselines[i]`. Lanes without a baseline
+// keep their default center.
+pub fn offset_lanes(tracks: &mut [Vec<Vertex>], baselines: &[f32]) {
+    let lanes = tracks.len();
+
+    for (channel, (track, baseline)) in tracks.iter_mut().zip(baselines).enumerate() {
+        let shift = baseline - lane_center(channel, lanes);
+
+        for vertex in track.iter_mut() {
+            vertex.position[1] += shift;
+        }
+    }
+}
+
+// Rotates each lane so frame `i` lands in column `(i + offset) % len`, like a
+// CRT beam wrapping at the right edge. Lanes are split at the write head so
+// the newest and oldest samples aren't joined across the screen.
+pub fn sweep(tracks: Vec<Vec<Vertex>>, offset: usize) -> Vec<Vec<Vertex>> {
+    let mut swept = vec![];
+
+    for track in tracks {
+        let len = track.len();
+        if len == 0 {
+            continue;
+        }
+        let offset = offset % len;
+
+        let mut head: Vec<Vertex> = track
+            .iter()
+            .enumerate()
+            .map(|(i, vertex)| {
+                let column = (i + offset) % len;
+                let x = 2.0 * (column + 1) as f32 / len as f32 - 1.0;
+
+                Vertex {
+                    position: [x, vertex.position[1]],
+                    ..*vertex
+                }
+            })
+            .collect();
+
+        let wrapped = head.split_off(len - offset);
+        swept.push(head);
+        swept.push(wrapped);
+    }
+
+    swept
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum TriggerMode {
+    #[default]
+    Off,
+    RisingEdge,
+    FallingEdge,
+}
+
+// First index where `samples` cross `level` (-1..1) in the trigger direction
+// that still leaves `window` samples after it.
+pub fn find_trigger(
+    samples: &[f32],
+    window: usize,
+    mode: TriggerMode,
+    level: f32,
+) -> Option<usize> {
+    let level = level.clamp(-1.0, 1.0);
+    let last = samples.len().checked_sub(window)?;
+
+    (1..=last).find(|&i| {
+        let (before, after) = (samples[i - 1], samples[i]);
+        match mode {
+            TriggerMode::Off => false,
+            TriggerMode::RisingEdge => before < level && after >= level,
+            TriggerMode::FallingEdge => before > level && after <= level,
+        }
+    })
+}
+
+fn lane(channel: usize, channels: usize) -> (f32, f32) {
+    match (channels, channel) {
+        (2, 0) => (OUTPUT_START_LEFT, OUTPUT_END_LEFT),
+        (2, _) => (OUTPUT_START_RIGHT, OUTPUT_END_RIGHT),
+        _ => (OUTPUT_START, OUTPUT_END),
+    }
+}
+
+fn lane_center(channel: usize, channels: usize) -> f32 {
+    let (start, end) = lane(channel, channels);
+    (start + end) / 2.0
+}
+
+fn segment(start: [f32; 2], end: [f32; 2]) -> [Vertex; 2] {
+    [Vertex::new(start), Vertex::new(end)]
+}
+
+// Overlay geometry is drawn as a line list: every two vertices form a segment.
+pub fn generate_peak_ticks(held: &[f32]) -> Vec<Vertex> {
+    let mut ticks = vec![];
+
+    for (channel, peak) in held.iter().enumerate() {
+        let (start, end) = lane(channel, held.len());
+        let center = lane_center(channel, held.len());
+        let offset = peak.min(1.0) * (end - start) / 2.0;
+
+        for y in [center + offset, center - offset] {
+            ticks.extend(segment([TICK_START, y], [TICK_END, y]));
+        }
+    }
+
+    ticks
+}
+
+// Vertical bars rising from the bottom edge, right to left from just inside
+// the peak ticks, each filled to its level (0..1).
+pub fn generate_level_bars(levels: &[f32]) -> Vec<Vertex> {
+    let mut bars = vec![];
+
+    for (index, level) in levels.iter().enumerate() {
+        let right = LEVEL_BAR_RIGHT - index as f32 * (LEVEL_BAR_WIDTH + LEVEL_BAR_GAP);
+        let top = OUTPUT_START + (OUTPUT_END - OUTPUT_START) * level.clamp(0.0, 1.0);
+
+        for column in 0..LEVEL_BAR_COLUMNS {
+            let x = right - LEVEL_BAR_WIDTH * column as f32 / (LEVEL_BAR_COLUMNS - 1) as f32;
+            bars.extend(segment([x, OUTPUT_START], [x, top]));
+        }
+    }
+
+    bars
+}
+
+pub fn generate_cursor_line(x: f32) -> Vec<Vertex> {
+    segment([x, OUTPUT_START], [x, OUTPUT_END]).to_vec()
+}
+
+pub fn generate_progress_bar(fraction: f32) -> Vec<Vertex> {
+    let top = PROGRESS_HALF_HEIGHT;
+    let bottom = -PROGRESS_HALF_HEIGHT;
+    let filled = PROGRESS_LEFT + (PROGRESS_RIGHT - PROGRESS_LEFT) * fraction.clamp(0.0, 1.0);
+
+    let mut bar = vec![];
+
+    bar.extend(segment([PROGRESS_LEFT, top], [PROGRESS_RIGHT, top]));
+    bar.extend(segment([PROGRESS_RIGHT, top], [PROGRESS_RIGHT, bottom]));
+    bar.extend(segment([PROGRESS_RIGHT, bottom], [PROGRESS_LEFT, bottom]));
+    bar.extend(segment([PROGRESS_LEFT, bottom], [PROGRESS_LEFT, top]));
+
+    for row in 0..PROGRESS_ROWS {
+        let y = bottom + (top - bottom) * row as f32 / (PROGRESS_ROWS - 1) as f32;
+        bar.extend(segment([PROGRESS_LEFT, y], [filled, y]));
+    }
+
+    bar
+}
+
+// Horizontal bar near the bottom edge filled to `fraction`, with a tick at
+// `unity`.
+pub fn generate_volume_bar(fraction: f32, unity: f32) -> Vec<Vertex> {
+    let filled = PROGRESS_LEFT + (PROGRESS_RIGHT - PROGRESS_LEFT) * fraction.clamp(0.0, 1.0);
+    let unity = PROGRESS_LEFT + (PROGRESS_RIGHT - PROGRESS_LEFT) * unity.clamp(0.0, 1.0);
+
+    let mut bar = vec![];
+
+    bar.extend(segment(
+        [PROGRESS_LEFT, VOLUME_TOP],
+        [PROGRESS_RIGHT, VOLUME_TOP],
+    ));
+    bar.extend(segment(
+        [PROGRESS_RIGHT, VOLUME_TOP],
+        [PROGRESS_RIGHT, VOLUME_BOTTOM],
+    ));
+    bar.extend(segment(
+        [PROGRESS_RIGHT, VOLUME_BOTTOM],
+        [PROGRESS_LEFT, VOLUME_BOTTOM],
+    ));
+    bar.extend(segment(
+        [PROGRESS_LEFT, VOLUME_BOTTOM],
+        [PROGRESS_LEFT, VOLUME_TOP],
+    ));
+    bar.extend(segment(
+        [unity, VOLUME_TOP + 0.02],
+        [unity, VOLUME_BOTTOM - 0.02],
+    ));
+
+    for row in 0..VOLUME_ROWS {
+        let y =
+            VOLUME_BOTTOM + (VOLUME_TOP - VOLUME_BOTTOM) * row as f32 / (VOLUME_ROWS - 1) as f32;
+        bar.extend(segment([PROGRESS_LEFT, y], [filled, y]));
+    }
+
+    bar
+}
+
+// Greys overlay geometry towards black, for fading it out.
+pub fn dim(vertices: &mut [Vertex], intensity: f32) {
+    let intensity = intensity.clamp(0.0, 1.0);
+    for vertex in vertices.iter_mut() {
+        vertex.color = vertex.color.map(|channel| channel * intensity);
+    }
+}
+
+pub fn generate_trace(samples: &[f32], visible: usize) -> Vec<Vertex> {
+    let window = &samples[samples.len().saturating_sub(visible)..];
+    let step = 2.0 / (window.len().max(2) - 1) as f32;
+
+    window
+        .iter()
+        .enumerate()
+        .map(|(i, sample)| Vertex::new([OUTPUT_START + i as f32 * step, sample.clamp(-1.0, 1.0)]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rms_band_spans_the_block_level_about_the_lane() {
+        // A mono square wave of amplitude 0.5 has an RMS of 0.5.
+        let track: Vec<Vertex> = (0..RMS_BLOCK * 2)
+            .map(|i| Vertex::new([i as f32, if i % 4 < 2 { 0.5 } else { -0.5 }]))
+            .collect();
+
+        let bands = rms_bands(std::slice::from_ref(&track), &[]);
+
+        assert_eq!(bands.len(), 1);
+        assert_eq!(bands[0].len(), track.len());
+        for (i, (band, vertex)) in bands[0].iter().zip(&track).enumerate() {
+            let edge = if i % 2 == 0 { 0.5 } else { -0.5 };
+            assert_eq!(band.position, [vertex.position[0], edge]);
+            assert_eq!(band.color, [RMS_SHADE; 3]);
+        }
+    }
+}
@@ -0,0 +1,77 @@
+// Maps between horizontal pixel positions on the scope and track time. The
+// scope spans the full surface width, left edge at `start` seconds and right
+// edge at `end`.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct ViewTransform {
+    pub start: f64,
+    pub end: f64,
+}
+
+impl ViewTransform {
+    pub fn new(start: f64, end: f64) -> Self {
+        Self { start, end }
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.end - self.start
+    }
+
+    pub fn x_to_secs(&self, px: f32, width: u32) -> f64 {
+        self.start + self.duration() * px as f64 / width.max(1) as f64
+    }
+
+    pub fn secs_to_x(&self, secs: f64, width: u32) -> f32 {
+        if self.duration() <= 0.0 {
+            return 0.0;
+        }
+
+        ((secs - self.start) / self.duration() * width as f64) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixels_and_seconds_round_trip() {
+        let view = ViewTransform::new(0.0, 4.0);
+        for px in [0.0, 1.0, 250.5, 799.0, 800.0] {
+            let secs = view.x_to_secs(px, 800);
+            assert!((view.secs_to_x(secs, 800) - px).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn edges_map_to_the_window_bounds() {
+        let view = ViewTransform::new(1.5, 3.5);
+
+        assert_eq!(view.x_to_secs(0.0, 400), 1.5);
+        assert_eq!(view.x_to_secs(400.0, 400), 3.5);
+        assert_eq!(view.secs_to_x(1.5, 400), 0.0);
+        assert_eq!(view.secs_to_x(3.5, 400), 400.0);
+    }
+
+    #[test]
+    fn scrolled_and_zoomed_windows_shift_and_scale() {
+        // Scrolled 10s in and zoomed to a quarter of a second.
+        let view = ViewTransform::new(10.0, 10.25);
+
+        assert_eq!(view.x_to_secs(500.0, 1000), 10.125);
+        assert_eq!(view.secs_to_x(10.0625, 1000), 250.0);
+        // Times outside the window land off the surface.
+        assert!(view.secs_to_x(9.0, 1000) < 0.0);
+        assert!(view.secs_to_x(11.0, 1000) > 1000.0);
+    }
+
+    #[test]
+    fn degenerate_sizes_stay_finite() {
+        let view = ViewTransform::new(2.0, 6.0);
+        assert_eq!(view.x_to_secs(0.0, 0), 2.0);
+        assert_eq!(view.secs_to_x(4.0, 0), 0.0);
+
+        let empty = ViewTransform::new(3.0, 3.0);
+        assert_eq!(empty.x_to_secs(100.0, 200), 3.0);
+        assert_eq!(empty.secs_to_x(3.0, 200), 0.0);
+    }
+}